@@ -0,0 +1,751 @@
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, Read},
+    path::Path,
+};
+
+use rust_decimal::{Decimal, RoundingStrategy};
+
+use crate::model::Transaction;
+
+#[derive(Debug, thiserror::Error)]
+pub enum InputError {
+    #[error("line {line}: invalid row '{record}': {message}")]
+    InvalidRow {
+        line: u64,
+        record: String,
+        message: String,
+    },
+
+    #[error(
+        "line {line}: amount of transaction {tx} has more than {scale} decimal places: '{record}'"
+    )]
+    ExcessPrecision {
+        line: u64,
+        record: String,
+        tx: u32,
+        scale: u32,
+    },
+
+    #[error(transparent)]
+    Csv(#[from] csv::Error),
+
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+/// Path that stands for the standard input
+pub const STDIN: &str = "-";
+
+/// Whether the path stands for the standard input
+pub fn is_stdin(path: &Path) -> bool {
+    path.as_os_str() == STDIN
+}
+
+/// Compression of an input stream
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+    const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+    /// Infer the compression from a file extension, if it is a known one
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()? {
+            "gz" | "gzip" => Some(Self::Gzip),
+            "zst" | "zstd" => Some(Self::Zstd),
+            _ => None,
+        }
+    }
+
+    /// Detect the compression from the leading bytes of a stream
+    pub fn from_magic(bytes: &[u8]) -> Self {
+        if bytes.starts_with(&Self::GZIP_MAGIC) {
+            Self::Gzip
+        } else if bytes.starts_with(&Self::ZSTD_MAGIC) {
+            Self::Zstd
+        } else {
+            Self::None
+        }
+    }
+
+    /// Wrap a reader so it yields decompressed bytes as they are read
+    pub fn decoder<'a, R: BufRead + 'a>(self, reader: R) -> std::io::Result<Box<dyn Read + 'a>> {
+        Ok(match self {
+            Self::None => Box::new(reader),
+            Self::Gzip => Box::new(flate2::bufread::MultiGzDecoder::new(reader)),
+            Self::Zstd => Box::new(zstd::Decoder::with_buffer(reader)?),
+        })
+    }
+}
+
+/// Open an input path for streaming, where [`STDIN`] reads from the standard input.
+///
+/// Gzip and zstd inputs are decompressed on the fly. The compression is taken from the
+/// file extension, falling back to the magic bytes at the start of the stream.
+pub fn open(path: &Path) -> std::io::Result<Box<dyn Read>> {
+    let mut reader: Box<dyn BufRead> = if is_stdin(path) {
+        Box::new(std::io::stdin().lock())
+    } else {
+        Box::new(BufReader::new(File::open(path)?))
+    };
+
+    let compression = match Compression::from_path(path) {
+        Some(compression) => compression,
+        None => Compression::from_magic(reader.fill_buf()?),
+    };
+    compression.decoder(reader)
+}
+
+/// Formats transactions can be read from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum InputFormat {
+    Csv,
+    Jsonl,
+}
+
+impl InputFormat {
+    /// Infer the format from a file extension, if it is a known one.
+    ///
+    /// A compression extension is skipped, so `feed.jsonl.gz` is read as JSON Lines.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let path = match Compression::from_path(path) {
+            Some(_) => Path::new(path.file_stem()?),
+            None => path,
+        };
+        match path.extension()?.to_str()? {
+            "csv" => Some(Self::Csv),
+            "jsonl" | "ndjson" => Some(Self::Jsonl),
+            _ => None,
+        }
+    }
+}
+
+/// What to do with an amount given with more decimal places than the ledger keeps
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum PrecisionPolicy {
+    /// Drop the extra digits
+    #[default]
+    Truncate,
+
+    /// Round to the nearest, ties to the even neighbour
+    RoundHalfEven,
+
+    /// Reject the row
+    Reject,
+}
+
+/// Number of decimal places amounts are read with, and how extra ones are handled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Precision {
+    pub policy: PrecisionPolicy,
+    pub scale: u32,
+}
+
+impl Default for Precision {
+    fn default() -> Self {
+        Self {
+            policy: PrecisionPolicy::default(),
+            scale: Self::DEFAULT_SCALE,
+        }
+    }
+}
+
+impl Precision {
+    pub const DEFAULT_SCALE: u32 = 4;
+
+    /// Bring an amount to the configured scale, or `None` if the policy rejects it.
+    ///
+    /// Trailing zeros do not count as extra decimal places.
+    pub fn apply(&self, amount: Decimal) -> Option<Decimal> {
+        if amount.normalize().scale() <= self.scale {
+            return Some(amount);
+        }
+        match self.policy {
+            PrecisionPolicy::Truncate => {
+                Some(amount.round_dp_with_strategy(self.scale, RoundingStrategy::ToZero))
+            }
+            PrecisionPolicy::RoundHalfEven => Some(
+                amount.round_dp_with_strategy(self.scale, RoundingStrategy::MidpointNearestEven),
+            ),
+            PrecisionPolicy::Reject => None,
+        }
+    }
+
+    /// Apply the precision to the amount of a transaction read from `record` at `line`
+    fn apply_to(
+        &self,
+        line: u64,
+        record: &str,
+        mut tx: Transaction,
+    ) -> Result<(u64, Transaction), InputError> {
+        if let Some(amount) = tx.amount {
+            let amount = self
+                .apply(amount)
+                .ok_or_else(|| InputError::ExcessPrecision {
+                    line,
+                    record: record.to_string(),
+                    tx: tx.tx,
+                    scale: self.scale,
+                })?;
+            tx.amount = Some(amount);
+        }
+        Ok((line, tx))
+    }
+}
+
+/// Reads transactions one at a time from any [`Read`] source in a given [`InputFormat`].
+///
+/// Each item is either a transaction with the line it starts on, or an
+/// [`InputError::InvalidRow`] for a row that cannot be parsed, or an
+/// [`InputError::ExcessPrecision`] for an amount the [`Precision`] rejects, after which
+/// reading carries on with the next row. Any other error is fatal to the source.
+pub enum TransactionReader<R: Read> {
+    Csv {
+        reader: Box<csv::Reader<R>>,
+        headers: Option<csv::ByteRecord>,
+        record: csv::ByteRecord,
+        precision: Precision,
+    },
+    Jsonl {
+        reader: BufReader<R>,
+        line: u64,
+        buffer: Vec<u8>,
+        precision: Precision,
+    },
+}
+
+impl<R: Read> TransactionReader<R> {
+    /// Reader truncating amounts to the default precision
+    pub fn new(format: InputFormat, reader: R) -> Self {
+        Self::with_precision(format, reader, Precision::default())
+    }
+
+    pub fn with_precision(format: InputFormat, reader: R, precision: Precision) -> Self {
+        match format {
+            InputFormat::Csv => Self::Csv {
+                reader: Box::new(
+                    // the field count is checked per row, so a malformed row is still
+                    // reported with its content
+                    csv::ReaderBuilder::new()
+                        .trim(csv::Trim::All)
+                        .flexible(true)
+                        .from_reader(reader),
+                ),
+                headers: None,
+                record: csv::ByteRecord::new(),
+                precision,
+            },
+            InputFormat::Jsonl => Self::Jsonl {
+                reader: BufReader::new(reader),
+                line: 0,
+                buffer: Vec::new(),
+                precision,
+            },
+        }
+    }
+}
+
+fn next_csv<R: Read>(
+    reader: &mut csv::Reader<R>,
+    headers: &mut Option<csv::ByteRecord>,
+    record: &mut csv::ByteRecord,
+    precision: Precision,
+) -> Option<Result<(u64, Transaction), InputError>> {
+    if headers.is_none() {
+        match reader.byte_headers() {
+            Ok(read) => *headers = Some(read.clone()),
+            Err(err) => return Some(Err(err.into())),
+        }
+    }
+
+    let read = reader.read_byte_record(record);
+    let line = reader.position().line();
+    match read {
+        Ok(false) => None,
+        Ok(true) => {
+            let line = record.position().map_or(line, |p| p.line());
+            let raw = || {
+                record
+                    .iter()
+                    .map(String::from_utf8_lossy)
+                    .collect::<Vec<_>>()
+                    .join(",")
+            };
+            let fields = headers.as_ref().map_or(0, csv::ByteRecord::len);
+            if record.len() != fields {
+                return Some(Err(InputError::InvalidRow {
+                    line,
+                    record: raw(),
+                    message: format!(
+                        "found record with {} fields, but the header has {fields}",
+                        record.len()
+                    ),
+                }));
+            }
+            Some(
+                record
+                    .deserialize::<Transaction>(headers.as_ref())
+                    .map_err(|err| InputError::InvalidRow {
+                        line,
+                        record: raw(),
+                        message: err.to_string(),
+                    })
+                    .and_then(|tx| precision.apply_to(line, &raw(), tx)),
+            )
+        }
+        Err(err) if matches!(err.kind(), csv::ErrorKind::Io(_)) => Some(Err(err.into())),
+        Err(err) => Some(Err(InputError::InvalidRow {
+            line: err.position().map_or(line, |p| p.line()),
+            record: String::new(),
+            message: err.to_string(),
+        })),
+    }
+}
+
+fn next_jsonl<R: Read>(
+    reader: &mut BufReader<R>,
+    line: &mut u64,
+    buffer: &mut Vec<u8>,
+    precision: Precision,
+) -> Option<Result<(u64, Transaction), InputError>> {
+    loop {
+        buffer.clear();
+        match reader.read_until(b'\n', buffer) {
+            Ok(0) => return None,
+            Ok(_) => *line += 1,
+            Err(err) => return Some(Err(err.into())),
+        }
+
+        let row = match std::str::from_utf8(buffer) {
+            Ok(row) => row.trim(),
+            Err(err) => {
+                return Some(Err(InputError::InvalidRow {
+                    line: *line,
+                    record: String::from_utf8_lossy(buffer).trim().to_string(),
+                    message: err.to_string(),
+                }));
+            }
+        };
+        if row.is_empty() {
+            continue;
+        }
+        return Some(
+            serde_json::from_str::<Transaction>(row)
+                .map_err(|err| InputError::InvalidRow {
+                    line: *line,
+                    record: row.to_string(),
+                    message: err.to_string(),
+                })
+                .and_then(|tx| precision.apply_to(*line, row, tx)),
+        );
+    }
+}
+
+impl<R: Read> Iterator for TransactionReader<R> {
+    type Item = Result<(u64, Transaction), InputError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            Self::Csv {
+                reader,
+                headers,
+                record,
+                precision,
+            } => next_csv(reader, headers, record, *precision),
+            Self::Jsonl {
+                reader,
+                line,
+                buffer,
+                precision,
+            } => next_jsonl(reader, line, buffer, *precision),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::TransactionType;
+    use rust_decimal::Decimal;
+
+    fn read_all(format: InputFormat, input: &str) -> Vec<Result<(u64, Transaction), InputError>> {
+        TransactionReader::new(format, input.as_bytes()).collect()
+    }
+
+    #[test]
+    fn dash_stands_for_stdin() {
+        assert!(is_stdin(Path::new("-")));
+        assert!(!is_stdin(Path::new("transactions.csv")));
+        assert!(!is_stdin(Path::new("./-")));
+    }
+
+    #[test]
+    fn open_reads_files() {
+        let mut content = String::new();
+        open(Path::new(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/transactions.csv"
+        )))
+        .unwrap()
+        .read_to_string(&mut content)
+        .unwrap();
+        assert!(content.starts_with("type, client, tx, amount"));
+    }
+
+    #[test]
+    fn format_is_inferred_from_extension() {
+        assert_eq!(
+            InputFormat::from_path(Path::new("t.csv")),
+            Some(InputFormat::Csv)
+        );
+        assert_eq!(
+            InputFormat::from_path(Path::new("t.jsonl")),
+            Some(InputFormat::Jsonl)
+        );
+        assert_eq!(InputFormat::from_path(Path::new("t.txt")), None);
+    }
+
+    #[test]
+    fn format_is_inferred_through_compression_extension() {
+        assert_eq!(
+            InputFormat::from_path(Path::new("t.jsonl.gz")),
+            Some(InputFormat::Jsonl)
+        );
+        assert_eq!(
+            InputFormat::from_path(Path::new("dir/t.csv.zst")),
+            Some(InputFormat::Csv)
+        );
+        assert_eq!(InputFormat::from_path(Path::new("t.gz")), None);
+    }
+
+    mod compression_tests {
+        use super::*;
+        use std::io::Write;
+
+        const CSV: &str = "type,client,tx,amount\ndeposit,1,1,1.5\n";
+
+        fn gzip(data: &[u8]) -> Vec<u8> {
+            let mut encoder =
+                flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+            encoder.write_all(data).unwrap();
+            encoder.finish().unwrap()
+        }
+
+        fn decode(compression: Compression, data: &[u8]) -> String {
+            let mut content = String::new();
+            compression
+                .decoder(data)
+                .unwrap()
+                .read_to_string(&mut content)
+                .unwrap();
+            content
+        }
+
+        #[test]
+        fn compression_is_inferred_from_extension() {
+            assert_eq!(
+                Compression::from_path(Path::new("t.csv.gz")),
+                Some(Compression::Gzip)
+            );
+            assert_eq!(
+                Compression::from_path(Path::new("t.csv.zst")),
+                Some(Compression::Zstd)
+            );
+            assert_eq!(Compression::from_path(Path::new("t.csv")), None);
+        }
+
+        #[test]
+        fn compression_is_detected_from_magic_bytes() {
+            assert_eq!(Compression::from_magic(&gzip(b"x")), Compression::Gzip);
+            assert_eq!(
+                Compression::from_magic(&zstd::encode_all(&b"x"[..], 0).unwrap()),
+                Compression::Zstd
+            );
+            assert_eq!(Compression::from_magic(CSV.as_bytes()), Compression::None);
+            assert_eq!(Compression::from_magic(&[]), Compression::None);
+        }
+
+        #[test]
+        fn gzip_is_decoded() {
+            assert_eq!(decode(Compression::Gzip, &gzip(CSV.as_bytes())), CSV);
+        }
+
+        #[test]
+        fn concatenated_gzip_members_are_decoded() {
+            let mut data = gzip(b"type,client,tx,amount\n");
+            data.extend(gzip(b"deposit,1,1,1.5\n"));
+            assert_eq!(decode(Compression::Gzip, &data), CSV);
+        }
+
+        #[test]
+        fn zstd_is_decoded() {
+            let data = zstd::encode_all(CSV.as_bytes(), 0).unwrap();
+            assert_eq!(decode(Compression::Zstd, &data), CSV);
+        }
+
+        #[test]
+        fn compressed_transactions_are_read() {
+            let rows: Vec<_> = TransactionReader::new(
+                InputFormat::Csv,
+                Compression::Gzip
+                    .decoder(&gzip(CSV.as_bytes())[..])
+                    .unwrap(),
+            )
+            .collect();
+            assert_eq!(rows.len(), 1);
+            assert_eq!(
+                rows[0].as_ref().unwrap().1.amount,
+                Some(Decimal::new(15, 1))
+            );
+        }
+    }
+
+    mod csv_tests {
+        use super::*;
+
+        #[test]
+        fn reads_transactions_with_line_numbers() {
+            let rows = read_all(
+                InputFormat::Csv,
+                "type, client, tx, amount\ndeposit, 1, 1, 1.5\ndispute, 1, 1,\n",
+            );
+            let (line, tx) = rows[0].as_ref().unwrap();
+            assert_eq!(*line, 2);
+            assert_eq!(tx.r#type, TransactionType::Deposit);
+            assert_eq!(tx.amount, Some(Decimal::new(15, 1)));
+            let (line, tx) = rows[1].as_ref().unwrap();
+            assert_eq!(*line, 3);
+            assert_eq!(tx.amount, None);
+        }
+
+        #[test]
+        fn invalid_rows_are_reported_and_skipped() {
+            let rows = read_all(
+                InputFormat::Csv,
+                "type,client,tx,amount\nbogus,1,1,1\ndeposit,x,2,1\ndeposit,1\ndeposit,1,3,1\n",
+            );
+            assert_eq!(rows.len(), 4);
+            assert!(matches!(
+                &rows[0],
+                Err(InputError::InvalidRow { line: 2, record, .. }) if record == "bogus,1,1,1"
+            ));
+            assert!(matches!(
+                &rows[1],
+                Err(InputError::InvalidRow { line: 3, .. })
+            ));
+            assert!(matches!(
+                &rows[2],
+                Err(InputError::InvalidRow { line: 4, record, .. }) if record == "deposit,1"
+            ));
+            assert_eq!(rows[3].as_ref().unwrap().0, 5);
+        }
+
+        #[test]
+        fn rows_with_invalid_utf8_keep_their_content() {
+            let rows: Vec<_> = TransactionReader::new(
+                InputFormat::Csv,
+                &b"type,client,tx,amount\ndeposit,1,1,1\xff\ndeposit,1,2,1\n"[..],
+            )
+            .collect();
+            assert!(matches!(
+                &rows[0],
+                Err(InputError::InvalidRow { line: 2, record, .. }) if record == "deposit,1,1,1\u{fffd}"
+            ));
+            assert_eq!(rows[1].as_ref().unwrap().0, 3);
+        }
+
+        #[test]
+        fn rows_with_extra_fields_keep_their_content() {
+            let rows = read_all(
+                InputFormat::Csv,
+                "type,client,tx,amount
+deposit,1,1,1,oops
+deposit,1,2,1
+",
+            );
+            assert!(matches!(
+                &rows[0],
+                Err(InputError::InvalidRow { line: 2, record, message })
+                    if record == "deposit,1,1,1,oops" && message.contains("5 fields")
+            ));
+            assert_eq!(rows[1].as_ref().unwrap().0, 3);
+        }
+
+        #[test]
+        fn reads_optional_metadata_columns() {
+            let rows = read_all(
+                InputFormat::Csv,
+                "type,client,tx,amount,timestamp,currency,reference,memo\n\
+                 deposit,1,1,1.5,1700000000,EUR,INV-7,first payment\n\
+                 dispute,1,1,,,,,\n",
+            );
+            let (_, tx) = rows[0].as_ref().unwrap();
+            assert_eq!(tx.timestamp, Some(1_700_000_000));
+            assert_eq!(tx.currency.as_deref(), Some("EUR"));
+            assert_eq!(tx.reference.as_deref(), Some("INV-7"));
+            assert_eq!(tx.memo.as_deref(), Some("first payment"));
+            let (_, tx) = rows[1].as_ref().unwrap();
+            assert_eq!(tx.timestamp, None);
+            assert_eq!(tx.currency, None);
+            assert_eq!(tx.reference, None);
+            assert_eq!(tx.memo, None);
+        }
+    }
+
+    mod jsonl_tests {
+        use super::*;
+
+        #[test]
+        fn reads_transactions_with_line_numbers() {
+            let rows = read_all(
+                InputFormat::Jsonl,
+                "{\"type\":\"deposit\",\"client\":1,\"tx\":1,\"amount\":\"1.5\"}\n\n\
+                 {\"type\":\"dispute\",\"client\":1,\"tx\":1}\n",
+            );
+            assert_eq!(rows.len(), 2);
+            let (line, tx) = rows[0].as_ref().unwrap();
+            assert_eq!(*line, 1);
+            assert_eq!(tx.amount, Some(Decimal::new(15, 1)));
+            let (line, tx) = rows[1].as_ref().unwrap();
+            assert_eq!(*line, 3);
+            assert_eq!(tx.r#type, TransactionType::Dispute);
+            assert_eq!(tx.amount, None);
+        }
+
+        #[test]
+        fn invalid_rows_are_reported_and_skipped() {
+            let rows = read_all(
+                InputFormat::Jsonl,
+                "{\"type\":\"bogus\",\"client\":1,\"tx\":1}\nnot json\n\
+                 {\"type\":\"deposit\",\"client\":1,\"tx\":2,\"amount\":1}\n",
+            );
+            assert_eq!(rows.len(), 3);
+            assert!(matches!(
+                &rows[0],
+                Err(InputError::InvalidRow { line: 1, .. })
+            ));
+            assert!(matches!(
+                &rows[1],
+                Err(InputError::InvalidRow { line: 2, record, .. }) if record == "not json"
+            ));
+            assert_eq!(rows[2].as_ref().unwrap().0, 3);
+        }
+
+        #[test]
+        fn rows_with_invalid_utf8_are_reported_and_skipped() {
+            let rows: Vec<_> = TransactionReader::new(
+                InputFormat::Jsonl,
+                &b"{\"type\":\"deposit\",\"client\":1,\"tx\":1,\"memo\":\"\xff\"}\n\
+                   {\"type\":\"deposit\",\"client\":1,\"tx\":2,\"amount\":1}\n"[..],
+            )
+            .collect();
+            assert_eq!(rows.len(), 2);
+            assert!(matches!(
+                &rows[0],
+                Err(InputError::InvalidRow { line: 1, record, .. }) if record.contains('\u{fffd}')
+            ));
+            assert_eq!(rows[1].as_ref().unwrap().0, 2);
+        }
+    }
+
+    mod precision_tests {
+        use super::*;
+
+        fn precision(policy: PrecisionPolicy, scale: u32) -> Precision {
+            Precision { policy, scale }
+        }
+
+        #[test]
+        fn amounts_within_scale_are_kept() {
+            for policy in [
+                PrecisionPolicy::Truncate,
+                PrecisionPolicy::RoundHalfEven,
+                PrecisionPolicy::Reject,
+            ] {
+                let precision = precision(policy, 2);
+                assert_eq!(
+                    precision.apply(Decimal::new(125, 2)),
+                    Some(Decimal::new(125, 2))
+                );
+                // trailing zeros are not extra precision
+                assert_eq!(
+                    precision.apply(Decimal::new(12500, 4)),
+                    Some(Decimal::new(12500, 4))
+                );
+            }
+        }
+
+        #[test]
+        fn extra_decimal_places_follow_the_policy() {
+            let amount = Decimal::new(100005, 5);
+            assert_eq!(
+                precision(PrecisionPolicy::Truncate, 4).apply(amount),
+                Some(Decimal::ONE)
+            );
+            assert_eq!(
+                precision(PrecisionPolicy::RoundHalfEven, 4).apply(amount),
+                Some(Decimal::ONE)
+            );
+            assert_eq!(
+                precision(PrecisionPolicy::RoundHalfEven, 4).apply(Decimal::new(100015, 5)),
+                Some(Decimal::new(10002, 4))
+            );
+            assert_eq!(precision(PrecisionPolicy::Reject, 4).apply(amount), None);
+            assert_eq!(
+                precision(PrecisionPolicy::Truncate, 0).apply(Decimal::new(19, 1)),
+                Some(Decimal::ONE)
+            );
+        }
+
+        #[test]
+        fn default_truncates_to_four_places() {
+            let rows = read_all(
+                InputFormat::Csv,
+                "type,client,tx,amount\ndeposit,1,1,1.00009\n",
+            );
+            assert_eq!(rows[0].as_ref().unwrap().1.amount, Some(Decimal::ONE));
+        }
+
+        #[test]
+        fn rejected_rows_name_the_row_and_reading_carries_on() {
+            let input = "type,client,tx,amount\ndeposit,1,1,1.00001\ndeposit,1,2,1.0001\n";
+            let precision = precision(PrecisionPolicy::Reject, 4);
+            let rows: Vec<_> =
+                TransactionReader::with_precision(InputFormat::Csv, input.as_bytes(), precision)
+                    .collect();
+            assert!(matches!(
+                &rows[0],
+                Err(InputError::ExcessPrecision { line: 2, record, tx: 1, scale: 4 })
+                    if record == "deposit,1,1,1.00001"
+            ));
+            assert_eq!(
+                rows[1].as_ref().unwrap().1.amount,
+                Some(Decimal::new(10001, 4))
+            );
+
+            let input = "{\"type\":\"deposit\",\"client\":1,\"tx\":7,\"amount\":\"2.123\"}\n";
+            let precision = Precision {
+                policy: PrecisionPolicy::Reject,
+                scale: 2,
+            };
+            let rows: Vec<_> =
+                TransactionReader::with_precision(InputFormat::Jsonl, input.as_bytes(), precision)
+                    .collect();
+            assert!(matches!(
+                &rows[0],
+                Err(InputError::ExcessPrecision {
+                    line: 1,
+                    tx: 7,
+                    scale: 2,
+                    ..
+                })
+            ));
+        }
+    }
+}
@@ -0,0 +1,629 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufReader, BufWriter, ErrorKind, Read, Write},
+    path::Path,
+};
+
+use crate::{
+    Ledger,
+    model::{AccountError, Transaction, TransactionType},
+    rules::RuleError,
+};
+
+/// Size of the header preceding every journal record: payload length, CRC32 of the payload
+/// and CRC32 of those first two fields, all LE.
+const HEADER_LEN: u64 = 12;
+
+#[derive(Debug, thiserror::Error)]
+pub enum JournalError {
+    #[error("corrupt journal record at byte {0}")]
+    Corrupt(u64),
+
+    #[error("journal out of sequence: expected entry {expected}, found {found}")]
+    OutOfSequence { expected: u64, found: u64 },
+
+    #[error("journal entry {sequence} rejected on replay: {source}")]
+    Rejected { sequence: u64, source: AccountError },
+
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+/// Row of an input a transaction was read from
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Position {
+    pub input: String,
+    pub line: u64,
+}
+
+/// A transaction that changed the ledger, tagged with the ledger sequence it produced.
+///
+/// Besides accepted transactions, the journal keeps the transactions rejected by their
+/// account: the account is opened all the same, and a deposit or withdrawal consumes its ID.
+/// Those are tagged with the sequence the ledger was at.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct JournalEntry {
+    pub sequence: u64,
+    pub tx: Transaction,
+
+    /// Whether the account rejected the transaction
+    #[serde(default)]
+    pub rejected: bool,
+
+    /// Where the transaction was read, for an interrupted run to resume from
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub position: Option<Position>,
+}
+
+/// Append-only log of accepted transactions.
+///
+/// Each record is a little-endian `u32` payload length, the CRC32 of the payload, the CRC32
+/// of the length and payload CRC, and the payload itself, a JSON encoded [`JournalEntry`].
+/// Checking the header on its own tells a damaged length from a record cut short by a
+/// crash. Records are buffered and the file is
+/// fsynced every `batch` appends, on [`Journal::sync`] and when the journal is dropped.
+pub struct Journal {
+    writer: BufWriter<File>,
+    batch: usize,
+    pending: usize,
+}
+
+impl Journal {
+    /// Open the journal at `path`, creating it if needed, and recover its entries.
+    ///
+    /// A torn final record, left behind by a crash in the middle of an append, is dropped
+    /// and truncated away so that new records follow the last complete one.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`JournalError::Corrupt`] if a record other than the last one is damaged.
+    pub fn open(path: &Path, batch: usize) -> Result<(Self, Vec<JournalEntry>), JournalError> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        let len = file.metadata()?.len();
+        let (entries, valid_len) = read_entries(BufReader::new(&mut file), len)?;
+        if valid_len < len {
+            file.set_len(valid_len)?;
+            file.sync_data()?;
+        }
+
+        let file = OpenOptions::new().append(true).open(path)?;
+        let journal = Self {
+            writer: BufWriter::new(file),
+            batch: batch.max(1),
+            pending: 0,
+        };
+        Ok((journal, entries))
+    }
+
+    /// Append an entry, syncing to disk once a full batch is pending
+    pub fn append(&mut self, entry: &JournalEntry) -> Result<(), JournalError> {
+        let payload = serde_json::to_vec(entry)?;
+        let len = u32::try_from(payload.len())
+            .map_err(|_| std::io::Error::new(ErrorKind::InvalidInput, "journal entry too large"))?;
+        let mut header = [0; HEADER_LEN as usize];
+        header[..4].copy_from_slice(&len.to_le_bytes());
+        header[4..8].copy_from_slice(&crc32fast::hash(&payload).to_le_bytes());
+        let header_crc = crc32fast::hash(&header[..8]);
+        header[8..].copy_from_slice(&header_crc.to_le_bytes());
+        self.writer.write_all(&header)?;
+        self.writer.write_all(&payload)?;
+
+        self.pending += 1;
+        if self.pending >= self.batch {
+            self.sync()?;
+        }
+        Ok(())
+    }
+
+    /// Journal the outcome of applying a transaction read at `position` to `ledger`: an
+    /// accepted transaction, or one rejected by its account, which still opened the account
+    /// and consumed the ID of a deposit or withdrawal. A duplicate ID never reaches an account
+    /// and a store failure is fatal to the run, so neither journals anything.
+    pub fn record(
+        &mut self,
+        ledger: &Ledger,
+        position: Position,
+        tx: &Transaction,
+        result: &Result<(), AccountError>,
+    ) -> Result<(), JournalError> {
+        let rejected = match result {
+            Ok(()) => false,
+            Err(AccountError::Store(_)) => return Ok(()),
+            Err(AccountError::RuleViolation(RuleError::DuplicateTransaction(_))) => return Ok(()),
+            Err(_) => true,
+        };
+        self.append(&JournalEntry {
+            sequence: ledger.sequence(),
+            tx: tx.clone(),
+            rejected,
+            position: Some(position),
+        })
+    }
+
+    /// Flush pending entries and fsync them to disk
+    pub fn sync(&mut self) -> Result<(), JournalError> {
+        self.writer.flush()?;
+        self.writer.get_ref().sync_data()?;
+        self.pending = 0;
+        Ok(())
+    }
+
+    /// Drop every entry, once they are all covered by a saved ledger state
+    pub fn clear(&mut self) -> Result<(), JournalError> {
+        self.sync()?;
+        self.writer.get_ref().set_len(0)?;
+        self.writer.get_ref().sync_data()?;
+        Ok(())
+    }
+}
+
+impl Drop for Journal {
+    fn drop(&mut self) {
+        let _ = self.sync();
+    }
+}
+
+/// Read a record header, or `None` at a clean or torn end of input.
+///
+/// Returns the payload length and CRC32 once the header checksum is verified.
+fn read_header<R: Read>(reader: &mut R, offset: u64) -> Result<Option<(u32, u32)>, JournalError> {
+    let mut header = [0; HEADER_LEN as usize];
+    match reader.read_exact(&mut header) {
+        Ok(()) => {}
+        Err(err) if err.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(err) => return Err(err.into()),
+    }
+    let field = |at: usize| u32::from_le_bytes(header[at..at + 4].try_into().unwrap());
+    if crc32fast::hash(&header[..8]) != field(8) {
+        return Err(JournalError::Corrupt(offset));
+    }
+    Ok(Some((field(0), field(4))))
+}
+
+/// Decode the records of a journal of `len` bytes.
+///
+/// Returns the complete entries along with the length of the journal they span; anything
+/// past it is a torn final record. A damaged header is an error even at the end: a crash
+/// cuts a record short but does not alter what was written.
+fn read_entries<R: Read>(
+    mut reader: R,
+    len: u64,
+) -> Result<(Vec<JournalEntry>, u64), JournalError> {
+    let mut entries = Vec::new();
+    let mut offset = 0;
+    let mut payload = Vec::new();
+
+    while offset < len {
+        let Some((size, crc)) = read_header(&mut reader, offset)? else {
+            break;
+        };
+        let end = offset + HEADER_LEN + u64::from(size);
+        if end > len {
+            break;
+        }
+
+        payload.resize(size as usize, 0);
+        reader.read_exact(&mut payload)?;
+        let entry = (crc32fast::hash(&payload) == crc)
+            .then(|| serde_json::from_slice::<JournalEntry>(&payload).ok())
+            .flatten();
+        match entry {
+            Some(entry) => entries.push(entry),
+            None if end == len => break,
+            None => return Err(JournalError::Corrupt(offset)),
+        }
+        offset = end;
+    }
+
+    Ok((entries, offset))
+}
+
+impl Ledger {
+    /// Re-apply journal entries on top of the current state.
+    ///
+    /// Entries already covered by the ledger sequence, for instance by a saved state, are
+    /// skipped.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`JournalError::OutOfSequence`] if an entry is missing, and
+    /// [`JournalError::Rejected`] if an entry does not apply, which means the journal does not
+    /// belong to this ledger.
+    pub fn replay<I>(&mut self, entries: I) -> Result<(), JournalError>
+    where
+        I: IntoIterator<Item = JournalEntry>,
+    {
+        for entry in entries {
+            if entry.rejected {
+                if entry.sequence > self.sequence() {
+                    return Err(JournalError::OutOfSequence {
+                        expected: self.sequence(),
+                        found: entry.sequence,
+                    });
+                }
+                // registering the ID and opening the account again are harmless when the
+                // state already covers them
+                if matches!(
+                    entry.tx.r#type,
+                    TransactionType::Deposit | TransactionType::Withdrawal
+                ) {
+                    self.tx_ids.insert(entry.tx.tx);
+                }
+                self.open_account(entry.tx.client);
+                continue;
+            }
+            if entry.sequence <= self.sequence() {
+                continue;
+            }
+            let expected = self.sequence() + 1;
+            if entry.sequence != expected {
+                return Err(JournalError::OutOfSequence {
+                    expected,
+                    found: entry.sequence,
+                });
+            }
+            self.apply(entry.tx)
+                .map_err(|source| JournalError::Rejected {
+                    sequence: entry.sequence,
+                    source,
+                })?;
+        }
+        Ok(())
+    }
+}
+
+/// Where an interrupted run stopped: the position of the last transaction it journaled.
+///
+/// Re-running the same inputs skips every row up to that position, so the run carries on
+/// exactly where it left off. A journal written for other inputs skips nothing.
+#[derive(Debug, Default)]
+pub struct Resume {
+    position: Option<Position>,
+    reached: bool,
+}
+
+impl Resume {
+    /// Resume point of the journal `entries` for a run over `inputs`
+    pub fn new<S: AsRef<str>>(entries: &[JournalEntry], inputs: &[S]) -> Self {
+        let position = entries
+            .iter()
+            .rev()
+            .find_map(|entry| entry.position.clone())
+            .filter(|position| inputs.iter().any(|input| input.as_ref() == position.input));
+        Self {
+            position,
+            reached: false,
+        }
+    }
+
+    /// Whether the row at `line` of `input` was already processed by the interrupted run.
+    ///
+    /// Rows must be given in input order.
+    pub fn skips(&mut self, input: &str, line: u64) -> bool {
+        let Some(position) = &self.position else {
+            return false;
+        };
+        if position.input == input {
+            self.reached = true;
+            return line <= position.line;
+        }
+        if self.reached {
+            self.position = None;
+            return false;
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::TransactionType;
+    use rust_decimal::Decimal;
+
+    fn make_entry(sequence: u64, r#type: TransactionType, tx: u32) -> JournalEntry {
+        JournalEntry {
+            sequence,
+            rejected: false,
+            position: None,
+            tx: Transaction::new(
+                r#type,
+                1,
+                tx,
+                (r#type == TransactionType::Deposit).then(|| Decimal::new(12345, 2)),
+            ),
+        }
+    }
+
+    fn make_entries() -> Vec<JournalEntry> {
+        vec![
+            make_entry(1, TransactionType::Deposit, 1),
+            make_entry(2, TransactionType::Deposit, 2),
+            make_entry(3, TransactionType::Dispute, 1),
+        ]
+    }
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("tx-cli-journal-{}-{name}", std::process::id()))
+    }
+
+    /// Write entries to a fresh journal and return its path along with record offsets
+    fn write_journal(name: &str, entries: &[JournalEntry]) -> (std::path::PathBuf, Vec<u64>) {
+        let path = temp_path(name);
+        let _ = std::fs::remove_file(&path);
+        let mut offsets = Vec::new();
+        let (mut journal, _) = Journal::open(&path, 1).unwrap();
+        for entry in entries {
+            offsets.push(std::fs::metadata(&path).unwrap().len());
+            journal.append(entry).unwrap();
+        }
+        drop(journal);
+        (path, offsets)
+    }
+
+    #[test]
+    fn entries_round_trip() {
+        let (path, _) = write_journal("round-trip", &make_entries());
+        let (_, entries) = Journal::open(&path, 1).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(entries, make_entries());
+    }
+
+    #[test]
+    fn appends_follow_recovered_entries() {
+        let entries = make_entries();
+        let (path, _) = write_journal("append", &entries[..2]);
+        let (mut journal, recovered) = Journal::open(&path, 10).unwrap();
+        assert_eq!(recovered, entries[..2]);
+        journal.append(&entries[2]).unwrap();
+        drop(journal);
+        let (_, recovered) = Journal::open(&path, 1).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(recovered, entries);
+    }
+
+    #[test]
+    fn torn_final_record_is_dropped_at_every_offset() {
+        let entries = make_entries();
+        let (path, offsets) = write_journal("torn", &entries);
+        let bytes = std::fs::read(&path).unwrap();
+        let last = offsets[2] as usize;
+
+        for cut in last..bytes.len() {
+            std::fs::write(&path, &bytes[..cut]).unwrap();
+            let (journal, recovered) = Journal::open(&path, 1).unwrap();
+            drop(journal);
+            assert_eq!(recovered, entries[..2], "cut at byte {cut}");
+            assert_eq!(
+                std::fs::metadata(&path).unwrap().len(),
+                last as u64,
+                "cut at byte {cut}"
+            );
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn damaged_final_record_is_dropped() {
+        let entries = make_entries();
+        let (path, _) = write_journal("damaged-last", &entries);
+        let mut bytes = std::fs::read(&path).unwrap();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0xff;
+        std::fs::write(&path, &bytes).unwrap();
+        let (_, recovered) = Journal::open(&path, 1).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(recovered, entries[..2]);
+    }
+
+    #[test]
+    fn damaged_inner_record_is_an_error() {
+        let (path, offsets) = write_journal("damaged-inner", &make_entries());
+        let mut bytes = std::fs::read(&path).unwrap();
+        bytes[offsets[1] as usize + HEADER_LEN as usize] ^= 0xff;
+        std::fs::write(&path, &bytes).unwrap();
+        let result = Journal::open(&path, 1);
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(result, Err(JournalError::Corrupt(offset)) if offset == offsets[1]));
+    }
+
+    #[test]
+    fn damaged_inner_length_is_an_error() {
+        let (path, offsets) = write_journal("damaged-length", &make_entries());
+        let mut bytes = std::fs::read(&path).unwrap();
+        // a length running past the end of the journal must not pass for a torn record
+        bytes[offsets[1] as usize + 3] = 0xff;
+        std::fs::write(&path, &bytes).unwrap();
+        let result = Journal::open(&path, 1);
+        let len = std::fs::metadata(&path).unwrap().len();
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(result, Err(JournalError::Corrupt(offset)) if offset == offsets[1]));
+        assert_eq!(len, bytes.len() as u64);
+    }
+
+    #[test]
+    fn clear_drops_all_entries() {
+        let (path, _) = write_journal("clear", &make_entries());
+        let (mut journal, _) = Journal::open(&path, 1).unwrap();
+        journal.clear().unwrap();
+        drop(journal);
+        let (_, recovered) = Journal::open(&path, 1).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(recovered.is_empty());
+    }
+
+    mod recovery_tests {
+        use super::*;
+
+        const INPUT: &str = "feed.csv";
+
+        fn make_tx(r#type: TransactionType, tx: u32, amount: i64) -> Transaction {
+            Transaction::new(r#type, 1, tx, (amount > 0).then(|| Decimal::from(amount)))
+        }
+
+        /// A withdrawal rejected for lack of funds whose ID comes back later, a dispute
+        /// rejected because it comes before its deposit, and rejected rows that open the
+        /// accounts of clients 9 and 8
+        fn make_rows() -> Vec<(u64, Transaction)> {
+            [
+                make_tx(TransactionType::Deposit, 1, 5),
+                make_tx(TransactionType::Withdrawal, 2, 10),
+                make_tx(TransactionType::Deposit, 3, 100),
+                make_tx(TransactionType::Dispute, 4, 0),
+                Transaction {
+                    client: 9,
+                    ..make_tx(TransactionType::Withdrawal, 5, 5)
+                },
+                Transaction {
+                    client: 8,
+                    ..make_tx(TransactionType::Dispute, 1, 0)
+                },
+                make_tx(TransactionType::Deposit, 4, 10),
+                make_tx(TransactionType::Withdrawal, 2, 1),
+            ]
+            .into_iter()
+            .enumerate()
+            .map(|(index, tx)| (index as u64 + 2, tx))
+            .collect()
+        }
+
+        /// Process `rows` the way the binary does, crashing after the first `crash_after`
+        fn run(path: &Path, rows: &[(u64, Transaction)], crash_after: usize) -> Ledger {
+            let (mut journal, entries) = Journal::open(path, 1).unwrap();
+            let mut resume = Resume::new(&entries, &[INPUT]);
+            let mut ledger = Ledger::new();
+            ledger.replay(entries).unwrap();
+            for (line, tx) in rows.iter().take(crash_after) {
+                if resume.skips(INPUT, *line) {
+                    continue;
+                }
+                let result = ledger.apply(tx.clone());
+                let position = Position {
+                    input: INPUT.to_string(),
+                    line: *line,
+                };
+                journal.record(&ledger, position, tx, &result).unwrap();
+            }
+            ledger
+        }
+
+        #[test]
+        fn rerun_after_a_crash_matches_an_uninterrupted_run() {
+            let rows = make_rows();
+            let path = temp_path("recovery");
+            let _ = std::fs::remove_file(&path);
+            let uninterrupted = run(&path, &rows, rows.len());
+            assert_eq!(
+                uninterrupted.account(1).unwrap().balance(None).available,
+                Decimal::from(115)
+            );
+            assert_eq!(uninterrupted.accounts().count(), 3);
+
+            for crash_after in 0..=rows.len() {
+                std::fs::remove_file(&path).unwrap();
+                run(&path, &rows, crash_after);
+                let recovered = run(&path, &rows, rows.len());
+                assert_eq!(
+                    recovered.snapshot(),
+                    uninterrupted.snapshot(),
+                    "crash after {crash_after} rows"
+                );
+                assert_eq!(recovered.sequence(), uninterrupted.sequence());
+            }
+            std::fs::remove_file(&path).unwrap();
+        }
+
+        #[test]
+        fn replay_keeps_ids_of_rejected_withdrawals() {
+            let rows = make_rows();
+            let path = temp_path("rejected-ids");
+            let _ = std::fs::remove_file(&path);
+            run(&path, &rows, 2);
+            let (_, entries) = Journal::open(&path, 1).unwrap();
+            std::fs::remove_file(&path).unwrap();
+            assert!(entries[1].rejected);
+            let mut ledger = Ledger::new();
+            ledger.replay(entries).unwrap();
+            assert!(ledger.tx_ids.contains(2));
+            assert_eq!(ledger.sequence(), 1);
+        }
+
+        #[test]
+        fn resume_skips_rows_up_to_the_last_entry() {
+            let entry = |input: &str, line| JournalEntry {
+                position: Some(Position {
+                    input: input.to_string(),
+                    line,
+                }),
+                ..make_entry(1, TransactionType::Deposit, 1)
+            };
+            let mut resume = Resume::new(&[entry("b.csv", 3)], &["a.csv", "b.csv", "c.csv"]);
+            assert!(resume.skips("a.csv", 9));
+            assert!(resume.skips("b.csv", 3));
+            assert!(!resume.skips("b.csv", 4));
+            assert!(!resume.skips("c.csv", 1));
+
+            // a journal of other inputs skips nothing
+            let mut resume = Resume::new(&[entry("old.csv", 3)], &["a.csv"]);
+            assert!(!resume.skips("a.csv", 2));
+        }
+    }
+
+    mod replay_tests {
+        use super::*;
+
+        #[test]
+        fn replay_rebuilds_ledger() {
+            let mut ledger = Ledger::new();
+            ledger.replay(make_entries()).unwrap();
+            assert_eq!(ledger.sequence(), 3);
+            let account = ledger.account(1).unwrap();
+            assert_eq!(account.balance(None).held, Decimal::new(12345, 2));
+            assert_eq!(account.balance(None).available, Decimal::new(12345, 2));
+        }
+
+        #[test]
+        fn replay_skips_entries_covered_by_state() {
+            let entries = make_entries();
+            let mut ledger = Ledger::new();
+            ledger.replay(entries[..2].to_vec()).unwrap();
+            ledger.replay(entries).unwrap();
+            assert_eq!(ledger.sequence(), 3);
+            assert_eq!(
+                ledger.account(1).unwrap().balance(None).total(),
+                Decimal::new(24690, 2)
+            );
+        }
+
+        #[test]
+        fn replay_detects_missing_entries() {
+            let mut entries = make_entries();
+            entries.remove(1);
+            let mut ledger = Ledger::new();
+            assert!(matches!(
+                ledger.replay(entries),
+                Err(JournalError::OutOfSequence {
+                    expected: 2,
+                    found: 3
+                })
+            ));
+        }
+
+        #[test]
+        fn replay_rejects_foreign_entries() {
+            let mut ledger = Ledger::new();
+            assert!(matches!(
+                ledger.replay([make_entry(1, TransactionType::Dispute, 1)]),
+                Err(JournalError::Rejected { sequence: 1, .. })
+            ));
+        }
+    }
+}
@@ -0,0 +1,757 @@
+use std::collections::HashMap;
+
+use rust_decimal::Decimal;
+
+use crate::{
+    limits::Limits,
+    model::{Account, AccountError, Transaction, TransactionType},
+    policy::Policy,
+    rules::{self, RuleError},
+    store::{Storage, TxIdSet},
+};
+
+/// Point-in-time view of an account balance in one currency, detached from the ledger.
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct AccountSnapshot {
+    pub client: u16,
+    pub currency: Option<String>,
+    pub available: Decimal,
+    pub held: Decimal,
+    pub total: Decimal,
+    pub locked: bool,
+}
+
+impl AccountSnapshot {
+    /// Views of every balance of the account, sorted by currency
+    pub fn of(account: &Account) -> impl Iterator<Item = Self> + '_ {
+        account
+            .currency_balances()
+            .into_iter()
+            .map(|(currency, balance)| Self {
+                client: account.client,
+                currency: currency.map(str::to_string),
+                available: balance.available,
+                held: balance.held,
+                total: balance.total(),
+                locked: account.frozen,
+            })
+    }
+}
+
+/// Accounts of a ledger that record their history
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub(crate) enum Histories {
+    #[default]
+    None,
+    Client(u16),
+    All,
+}
+
+impl Histories {
+    fn includes(self, client: u16) -> bool {
+        match self {
+            Self::None => false,
+            Self::Client(recorded) => recorded == client,
+            Self::All => true,
+        }
+    }
+}
+
+/// Owns every client account and routes transactions to them.
+#[derive(Debug, Default)]
+pub struct Ledger {
+    pub(crate) accounts: HashMap<u16, Account>,
+    pub(crate) policy: Policy,
+
+    /// Where newly opened accounts keep their transactions
+    pub(crate) storage: Storage,
+
+    /// Credit lines of the clients allowed to overdraw their account
+    pub(crate) limits: Limits,
+
+    /// Accounts that record their history
+    pub(crate) histories: Histories,
+
+    /// IDs of every deposit and withdrawal seen, across all clients
+    pub(crate) tx_ids: TxIdSet,
+
+    /// Number of transactions accepted so far
+    pub(crate) sequence: u64,
+}
+
+impl Ledger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Ledger whose accounts are all opened with the given policy
+    pub fn with_policy(policy: Policy) -> Self {
+        Self {
+            policy,
+            ..Default::default()
+        }
+    }
+
+    /// Ledger whose accounts are all opened with the given policy and storage
+    pub fn with_storage(policy: Policy, storage: Storage) -> Self {
+        Self {
+            policy,
+            storage,
+            ..Default::default()
+        }
+    }
+
+    /// Grant the given credit lines and withdrawal caps, to the existing accounts as well as
+    /// to the accounts opened later on
+    pub fn set_limits(&mut self, limits: Limits) {
+        for account in self.accounts.values_mut() {
+            account.limits = limits.get(account.client);
+        }
+        self.limits = limits;
+    }
+
+    /// Record the history of a client account from now on, opening it if needed later.
+    ///
+    /// Histories grow with every transaction, so unless [`Ledger::record_histories`] was
+    /// called only the account a statement is printed for keeps one.
+    pub fn record_history(&mut self, client: u16) {
+        if let Some(account) = self.accounts.get_mut(&client) {
+            account.record_history();
+        }
+        if self.histories != Histories::All {
+            self.histories = Histories::Client(client);
+        }
+    }
+
+    /// Record the history of every account from now on, the ones opened later included
+    pub fn record_histories(&mut self) {
+        for account in self.accounts.values_mut() {
+            account.record_history();
+        }
+        self.histories = Histories::All;
+    }
+
+    /// Applies a transaction to its client account, opening the account on first sight.
+    ///
+    /// Deposit and withdrawal IDs are unique ledger-wide: an ID is consumed as soon as the
+    /// ledger sees it, even if the transaction is then rejected by the account.
+    pub fn apply(&mut self, tx: Transaction) -> Result<(), AccountError> {
+        self.register(&tx)?;
+        self.apply_registered(tx)
+    }
+
+    /// Consumes the ID of a deposit or withdrawal in the ledger-wide registry.
+    pub(crate) fn register(&mut self, tx: &Transaction) -> Result<(), RuleError> {
+        if matches!(
+            tx.r#type,
+            TransactionType::Deposit | TransactionType::Withdrawal
+        ) {
+            rules::check_unique_transaction(&self.tx_ids, tx.tx)?;
+            self.tx_ids.insert(tx.tx);
+        }
+        Ok(())
+    }
+
+    /// The account of a client, opened with the ledger policy, storage and limits on first
+    /// sight.
+    pub(crate) fn open_account(&mut self, client: u16) -> &mut Account {
+        self.accounts.entry(client).or_insert_with(|| {
+            let mut account = Account::with_store(client, self.policy, self.storage.open_store());
+            account.limits = self.limits.get(client);
+            if self.histories.includes(client) {
+                account.record_history();
+            }
+            account
+        })
+    }
+
+    /// Applies a transaction whose ID already went through [`Ledger::register`].
+    pub(crate) fn apply_registered(&mut self, tx: Transaction) -> Result<(), AccountError> {
+        self.open_account(tx.client).process_transaction(tx)?;
+        self.sequence += 1;
+        Ok(())
+    }
+
+    /// Number of transactions accepted by the ledger so far
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    /// Return the account of the given client if it exists
+    pub fn account(&self, client: u16) -> Option<&Account> {
+        self.accounts.get(&client)
+    }
+
+    /// Iterate over all accounts in no particular order
+    pub fn accounts(&self) -> impl Iterator<Item = &Account> {
+        self.accounts.values()
+    }
+
+    /// Balances of all accounts sorted by client and currency ascending
+    pub fn snapshot(&self) -> Vec<AccountSnapshot> {
+        let mut snapshot: Vec<AccountSnapshot> =
+            self.accounts().flat_map(AccountSnapshot::of).collect();
+        snapshot.sort_by(|a, b| (a.client, &a.currency).cmp(&(b.client, &b.currency)));
+        snapshot
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    mod apply_tests {
+        use super::*;
+
+        #[test]
+        fn apply_opens_account_on_first_transaction() {
+            let mut ledger = Ledger::new();
+            assert!(ledger.account(1).is_none());
+            ledger
+                .apply(Transaction::new(
+                    TransactionType::Deposit,
+                    1,
+                    1,
+                    Some(Decimal::from(10)),
+                ))
+                .unwrap();
+            assert_eq!(
+                ledger.account(1).unwrap().balance(None).available,
+                Decimal::from(10)
+            );
+        }
+
+        #[test]
+        fn apply_routes_transactions_per_client() {
+            let mut ledger = Ledger::new();
+            ledger
+                .apply(Transaction::new(
+                    TransactionType::Deposit,
+                    1,
+                    1,
+                    Some(Decimal::from(10)),
+                ))
+                .unwrap();
+            ledger
+                .apply(Transaction::new(
+                    TransactionType::Deposit,
+                    2,
+                    2,
+                    Some(Decimal::from(20)),
+                ))
+                .unwrap();
+            assert_eq!(
+                ledger.account(1).unwrap().balance(None).available,
+                Decimal::from(10)
+            );
+            assert_eq!(
+                ledger.account(2).unwrap().balance(None).available,
+                Decimal::from(20)
+            );
+            assert_eq!(ledger.accounts().count(), 2);
+        }
+
+        #[test]
+        fn apply_counts_accepted_transactions_only() {
+            let mut ledger = Ledger::new();
+            ledger
+                .apply(Transaction::new(
+                    TransactionType::Deposit,
+                    1,
+                    1,
+                    Some(Decimal::from(10)),
+                ))
+                .unwrap();
+            assert!(
+                ledger
+                    .apply(Transaction::new(TransactionType::Dispute, 1, 9, None))
+                    .is_err()
+            );
+            assert_eq!(ledger.sequence(), 1);
+        }
+
+        #[test]
+        fn apply_opens_accounts_with_ledger_policy() {
+            let policy = Policy {
+                disputes: crate::policy::DisputePolicy::DepositsAndWithdrawals,
+                ..Default::default()
+            };
+            let mut ledger = Ledger::with_policy(policy);
+            ledger
+                .apply(Transaction::new(
+                    TransactionType::Deposit,
+                    1,
+                    1,
+                    Some(Decimal::from(10)),
+                ))
+                .unwrap();
+            assert_eq!(ledger.account(1).unwrap().policy, policy);
+        }
+
+        #[test]
+        fn apply_returns_rule_violation() {
+            let mut ledger = Ledger::new();
+            let result = ledger.apply(Transaction::new(
+                TransactionType::Withdrawal,
+                1,
+                1,
+                Some(Decimal::from(10)),
+            ));
+            assert!(matches!(result, Err(AccountError::RuleViolation(_))));
+        }
+    }
+
+    mod duplicate_transaction_tests {
+        use super::*;
+
+        #[test]
+        fn repeated_deposit_is_rejected_and_balance_unchanged() {
+            let mut ledger = Ledger::new();
+            ledger
+                .apply(Transaction::new(
+                    TransactionType::Deposit,
+                    1,
+                    1,
+                    Some(Decimal::from(10)),
+                ))
+                .unwrap();
+            let result = ledger.apply(Transaction::new(
+                TransactionType::Deposit,
+                1,
+                1,
+                Some(Decimal::from(99)),
+            ));
+            assert!(matches!(
+                result,
+                Err(AccountError::RuleViolation(
+                    RuleError::DuplicateTransaction(1)
+                ))
+            ));
+            assert_eq!(
+                ledger.account(1).unwrap().balance(None).available,
+                Decimal::from(10)
+            );
+            assert_eq!(
+                ledger
+                    .account(1)
+                    .unwrap()
+                    .find_transaction(&1)
+                    .unwrap()
+                    .unwrap()
+                    .amount,
+                Decimal::from(10)
+            );
+        }
+
+        #[test]
+        fn withdrawal_reusing_deposit_id_is_rejected() {
+            let mut ledger = Ledger::new();
+            ledger
+                .apply(Transaction::new(
+                    TransactionType::Deposit,
+                    1,
+                    1,
+                    Some(Decimal::from(10)),
+                ))
+                .unwrap();
+            let result = ledger.apply(Transaction::new(
+                TransactionType::Withdrawal,
+                1,
+                1,
+                Some(Decimal::from(5)),
+            ));
+            assert!(matches!(
+                result,
+                Err(AccountError::RuleViolation(
+                    RuleError::DuplicateTransaction(1)
+                ))
+            ));
+            assert_eq!(
+                ledger.account(1).unwrap().balance(None).available,
+                Decimal::from(10)
+            );
+        }
+
+        #[test]
+        fn deposit_id_collision_across_clients_is_rejected() {
+            let mut ledger = Ledger::new();
+            ledger
+                .apply(Transaction::new(
+                    TransactionType::Deposit,
+                    1,
+                    7,
+                    Some(Decimal::from(10)),
+                ))
+                .unwrap();
+            let result = ledger.apply(Transaction::new(
+                TransactionType::Deposit,
+                2,
+                7,
+                Some(Decimal::from(20)),
+            ));
+            assert!(matches!(
+                result,
+                Err(AccountError::RuleViolation(
+                    RuleError::DuplicateTransaction(7)
+                ))
+            ));
+            assert!(ledger.account(2).is_none());
+            assert_eq!(
+                ledger.account(1).unwrap().balance(None).available,
+                Decimal::from(10)
+            );
+        }
+
+        #[test]
+        fn withdrawal_id_collision_across_clients_is_rejected() {
+            let mut ledger = Ledger::new();
+            ledger
+                .apply(Transaction::new(
+                    TransactionType::Deposit,
+                    1,
+                    1,
+                    Some(Decimal::from(10)),
+                ))
+                .unwrap();
+            ledger
+                .apply(Transaction::new(
+                    TransactionType::Deposit,
+                    2,
+                    2,
+                    Some(Decimal::from(10)),
+                ))
+                .unwrap();
+            ledger
+                .apply(Transaction::new(
+                    TransactionType::Withdrawal,
+                    1,
+                    3,
+                    Some(Decimal::from(4)),
+                ))
+                .unwrap();
+            let result = ledger.apply(Transaction::new(
+                TransactionType::Withdrawal,
+                2,
+                3,
+                Some(Decimal::from(4)),
+            ));
+            assert!(matches!(
+                result,
+                Err(AccountError::RuleViolation(
+                    RuleError::DuplicateTransaction(3)
+                ))
+            ));
+            assert_eq!(
+                ledger.account(2).unwrap().balance(None).available,
+                Decimal::from(10)
+            );
+        }
+
+        #[test]
+        fn rejected_transaction_still_consumes_its_id() {
+            let mut ledger = Ledger::new();
+            assert!(
+                ledger
+                    .apply(Transaction::new(
+                        TransactionType::Withdrawal,
+                        1,
+                        1,
+                        Some(Decimal::from(10)),
+                    ))
+                    .is_err()
+            );
+            let result = ledger.apply(Transaction::new(
+                TransactionType::Deposit,
+                1,
+                1,
+                Some(Decimal::from(10)),
+            ));
+            assert!(matches!(
+                result,
+                Err(AccountError::RuleViolation(
+                    RuleError::DuplicateTransaction(1)
+                ))
+            ));
+        }
+
+        #[test]
+        fn dispute_references_are_not_duplicates() {
+            let mut ledger = Ledger::new();
+            ledger
+                .apply(Transaction::new(
+                    TransactionType::Deposit,
+                    1,
+                    1,
+                    Some(Decimal::from(10)),
+                ))
+                .unwrap();
+            ledger
+                .apply(Transaction::new(TransactionType::Dispute, 1, 1, None))
+                .unwrap();
+            ledger
+                .apply(Transaction::new(TransactionType::Resolve, 1, 1, None))
+                .unwrap();
+        }
+    }
+
+    mod snapshot_tests {
+        use super::*;
+
+        #[test]
+        fn snapshot_is_sorted_by_client() {
+            let mut ledger = Ledger::new();
+            for client in [3, 1, 2] {
+                ledger
+                    .apply(Transaction::new(
+                        TransactionType::Deposit,
+                        client,
+                        client as u32,
+                        Some(Decimal::from(5)),
+                    ))
+                    .unwrap();
+            }
+            let clients: Vec<u16> = ledger.snapshot().iter().map(|a| a.client).collect();
+            assert_eq!(clients, vec![1, 2, 3]);
+        }
+
+        #[test]
+        fn snapshot_reports_totals() {
+            let mut ledger = Ledger::new();
+            ledger
+                .apply(Transaction::new(
+                    TransactionType::Deposit,
+                    1,
+                    1,
+                    Some(Decimal::from(5)),
+                ))
+                .unwrap();
+            ledger
+                .apply(Transaction::new(TransactionType::Dispute, 1, 1, None))
+                .unwrap();
+            assert_eq!(
+                ledger.snapshot(),
+                vec![AccountSnapshot {
+                    client: 1,
+                    currency: None,
+                    available: Decimal::ZERO,
+                    held: Decimal::from(5),
+                    total: Decimal::from(5),
+                    locked: false,
+                }]
+            );
+        }
+
+        #[test]
+        fn limits_apply_to_existing_and_new_accounts() {
+            let mut ledger = Ledger::new();
+            ledger
+                .apply(Transaction::new(
+                    TransactionType::Deposit,
+                    1,
+                    1,
+                    Some(Decimal::ONE),
+                ))
+                .unwrap();
+            ledger.set_limits(Limits::from_reader("client,limit\n1,10\n2,5\n".as_bytes()).unwrap());
+            ledger
+                .apply(Transaction::new(
+                    TransactionType::Withdrawal,
+                    1,
+                    2,
+                    Some(Decimal::from(11)),
+                ))
+                .unwrap();
+            ledger
+                .apply(Transaction::new(
+                    TransactionType::Withdrawal,
+                    2,
+                    3,
+                    Some(Decimal::from(5)),
+                ))
+                .unwrap();
+            assert!(
+                ledger
+                    .apply(Transaction::new(
+                        TransactionType::Withdrawal,
+                        3,
+                        4,
+                        Some(Decimal::ONE)
+                    ))
+                    .is_err()
+            );
+            assert_eq!(
+                ledger.account(1).unwrap().balance(None).available,
+                Decimal::from(-10)
+            );
+            assert_eq!(
+                ledger.account(2).unwrap().balance(None).available,
+                Decimal::from(-5)
+            );
+        }
+
+        #[test]
+        fn only_the_requested_client_records_history() {
+            let mut ledger = Ledger::new();
+            ledger
+                .apply(Transaction::new(
+                    TransactionType::Deposit,
+                    1,
+                    1,
+                    Some(Decimal::ONE),
+                ))
+                .unwrap();
+            ledger.record_history(1);
+            ledger.record_history(2);
+            for (client, tx) in [(1, 2), (2, 3), (3, 4)] {
+                ledger
+                    .apply(Transaction::new(
+                        TransactionType::Deposit,
+                        client,
+                        tx,
+                        Some(Decimal::ONE),
+                    ))
+                    .unwrap();
+            }
+            // the existing account starts recording when asked, a new one when opened
+            assert_eq!(ledger.account(1).unwrap().history().len(), 2);
+            assert_eq!(ledger.account(2).unwrap().history().len(), 1);
+            assert!(ledger.account(3).unwrap().history().is_empty());
+        }
+
+        #[test]
+        fn every_account_records_history_when_asked() {
+            let mut ledger = Ledger::new();
+            ledger.record_histories();
+            ledger.record_history(1);
+            for (client, tx) in [(1, 1), (2, 2)] {
+                ledger
+                    .apply(Transaction::new(
+                        TransactionType::Deposit,
+                        client,
+                        tx,
+                        Some(Decimal::ONE),
+                    ))
+                    .unwrap();
+            }
+            assert_eq!(ledger.account(1).unwrap().history().len(), 1);
+            assert_eq!(ledger.account(2).unwrap().history().len(), 1);
+        }
+
+        #[test]
+        fn snapshot_has_one_entry_per_currency() {
+            let mut ledger = Ledger::new();
+            for (tx, currency) in [(1, Some("USD")), (2, None), (3, Some("EUR"))] {
+                let mut deposit =
+                    Transaction::new(TransactionType::Deposit, 1, tx, Some(Decimal::ONE));
+                deposit.currency = currency.map(str::to_string);
+                ledger.apply(deposit).unwrap();
+            }
+            let currencies: Vec<Option<String>> = ledger
+                .snapshot()
+                .into_iter()
+                .map(|snapshot| snapshot.currency)
+                .collect();
+            assert_eq!(
+                currencies,
+                vec![None, Some("EUR".to_string()), Some("USD".to_string())]
+            );
+        }
+    }
+
+    mod conservation_tests {
+        use super::*;
+        use crate::{model::TransactionKind, policy::DisputePolicy};
+        use proptest::prelude::*;
+
+        fn arb_tx() -> impl Strategy<Value = Transaction> {
+            let r#type = prop_oneof![
+                Just(TransactionType::Deposit),
+                Just(TransactionType::Withdrawal),
+                Just(TransactionType::Dispute),
+                Just(TransactionType::Resolve),
+                Just(TransactionType::Chargeback),
+            ];
+            // few clients and IDs so that disputes hit earlier transactions
+            let amount = prop::option::weighted(0.9, (-10_000i64..100_000, 0u32..=4))
+                .prop_map(|amount| amount.map(|(mantissa, scale)| Decimal::new(mantissa, scale)));
+            (r#type, 0u16..3, 0u32..24, amount).prop_map(|(r#type, client, tx, amount)| {
+                Transaction::new(r#type, client, tx, amount)
+            })
+        }
+
+        fn arb_policy() -> impl Strategy<Value = Policy> {
+            prop_oneof![
+                Just(DisputePolicy::DepositsOnly),
+                Just(DisputePolicy::DepositsAndWithdrawals),
+            ]
+            .prop_map(|disputes| Policy {
+                disputes,
+                ..Default::default()
+            })
+        }
+
+        proptest! {
+            /// Every account holds exactly what was deposited, less what was withdrawn and
+            /// charged back, plus the withdrawals returned by a dispute, so funds never appear
+            /// from nothing.
+            #[test]
+            fn accepted_transactions_conserve_money(
+                policy in arb_policy(),
+                stream in prop::collection::vec(arb_tx(), 0..200),
+            ) {
+                let mut ledger = Ledger::with_policy(policy);
+                let mut stored: HashMap<u32, (TransactionKind, Decimal)> = HashMap::new();
+                let mut expected: HashMap<u16, Decimal> = HashMap::new();
+                let mut deposited = Decimal::ZERO;
+
+                for tx in stream {
+                    if ledger.apply(tx.clone()).is_err() {
+                        continue;
+                    }
+                    let total = expected.entry(tx.client).or_default();
+                    match tx.r#type {
+                        TransactionType::Deposit => {
+                            let amount = tx.amount.unwrap();
+                            prop_assert!(amount > Decimal::ZERO);
+                            stored.insert(tx.tx, (TransactionKind::Deposit, amount));
+                            deposited += amount;
+                            *total += amount;
+                        }
+                        TransactionType::Withdrawal => {
+                            let amount = tx.amount.unwrap();
+                            prop_assert!(amount > Decimal::ZERO);
+                            stored.insert(tx.tx, (TransactionKind::Withdrawal, amount));
+                            *total -= amount;
+                        }
+                        // a disputed withdrawal is held as a pending credit until settled
+                        TransactionType::Dispute => {
+                            if let (TransactionKind::Withdrawal, amount) = stored[&tx.tx] {
+                                *total += amount;
+                            }
+                        }
+                        TransactionType::Resolve => {
+                            if let (TransactionKind::Withdrawal, amount) = stored[&tx.tx] {
+                                *total -= amount;
+                            }
+                        }
+                        TransactionType::Chargeback => {
+                            if let (TransactionKind::Deposit, amount) = stored[&tx.tx] {
+                                *total -= amount;
+                            }
+                        }
+                    }
+                }
+
+                let mut ledger_total = Decimal::ZERO;
+                for snapshot in ledger.snapshot() {
+                    // rejected transactions may still open an empty account
+                    let total = expected.get(&snapshot.client).copied().unwrap_or_default();
+                    prop_assert_eq!(snapshot.total, total);
+                    prop_assert!(snapshot.held >= Decimal::ZERO);
+                    ledger_total += snapshot.total;
+                }
+                prop_assert!(ledger_total <= deposited);
+            }
+        }
+    }
+}
@@ -0,0 +1,20 @@
+//! Transaction processing engine.
+//!
+//! The [`Ledger`] owns every client [`model::Account`] and applies incoming
+//! [`model::Transaction`]s to them, enforcing the validations in [`rules`].
+//! The `tx-cli` binary is a thin client of this API.
+
+pub mod input;
+pub mod journal;
+mod ledger;
+pub mod limits;
+pub mod model;
+pub mod output;
+pub mod policy;
+pub mod report;
+pub mod rules;
+pub mod shard;
+pub mod state;
+pub mod store;
+
+pub use ledger::*;
@@ -0,0 +1,292 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufReader, Read},
+    path::Path,
+};
+
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+use crate::policy::SECONDS_PER_DAY;
+
+#[derive(Debug, thiserror::Error)]
+pub enum LimitsError {
+    #[error("invalid limits on line {0}: limits must not be negative")]
+    InvalidLimit(u64),
+
+    #[error("invalid limits on line {0}: daily caps need a window_transactions column")]
+    MissingWindow(u64),
+
+    #[error(transparent)]
+    Csv(#[from] csv::Error),
+
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+/// Caps on the withdrawals of a client.
+///
+/// The window of the daily caps is the last day by timestamp when both withdrawals carry one.
+/// Otherwise it is measured by position: the window is the last `window_transactions`
+/// transactions of the account, and without it the daily caps do not apply.
+/// [`Limits::from_reader`] refuses daily caps without `window_transactions`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct VelocityLimits {
+    /// Largest amount of a single withdrawal
+    pub max_withdrawal: Option<Decimal>,
+
+    /// Largest amount withdrawn within a window, in each currency
+    pub daily_amount: Option<Decimal>,
+
+    /// Largest number of withdrawals within a window, in each currency
+    pub daily_count: Option<u32>,
+
+    /// Length of the window in transactions, for withdrawals without timestamp
+    pub window_transactions: Option<u64>,
+}
+
+/// A withdrawal accepted by an account, remembered for the length of the velocity window
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WithdrawalEntry {
+    /// Position of the withdrawal among the transactions of its account
+    pub sequence: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub currency: Option<String>,
+    pub amount: Decimal,
+}
+
+impl VelocityLimits {
+    /// Whether withdrawals must be remembered to enforce the limits
+    pub fn has_window(&self) -> bool {
+        self.daily_amount.is_some() || self.daily_count.is_some()
+    }
+
+    /// Whether an earlier withdrawal counts in the window of a withdrawal at `timestamp`,
+    /// the `position`th transaction of the account
+    pub fn in_window(
+        &self,
+        entry: &WithdrawalEntry,
+        timestamp: Option<u64>,
+        position: u64,
+    ) -> bool {
+        match (entry.timestamp, timestamp) {
+            (Some(then), Some(now)) => now.saturating_sub(then) < SECONDS_PER_DAY,
+            _ => self
+                .window_transactions
+                .is_some_and(|len| position.saturating_sub(entry.sequence) < len),
+        }
+    }
+
+    /// Whether an earlier withdrawal can no longer count in any window, given the latest
+    /// timestamp `clock` and the `position` of the account
+    pub fn has_expired(&self, entry: &WithdrawalEntry, clock: Option<u64>, position: u64) -> bool {
+        let by_time = match (entry.timestamp, clock) {
+            (Some(then), Some(now)) => now.saturating_sub(then) >= SECONDS_PER_DAY,
+            (Some(_), None) => false,
+            (None, _) => true,
+        };
+        by_time
+            && self
+                .window_transactions
+                .is_none_or(|len| position.saturating_sub(entry.sequence) >= len)
+    }
+}
+
+/// Limits granted to a single client.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ClientLimits {
+    /// How far below zero the available balance of each currency may go
+    pub credit: Decimal,
+    pub velocity: VelocityLimits,
+}
+
+#[derive(Deserialize)]
+struct LimitRow {
+    client: Option<u16>,
+    #[serde(default)]
+    limit: Option<Decimal>,
+    #[serde(default)]
+    max_withdrawal: Option<Decimal>,
+    #[serde(default)]
+    daily_amount: Option<Decimal>,
+    #[serde(default)]
+    daily_count: Option<u32>,
+    #[serde(default)]
+    window_transactions: Option<u64>,
+}
+
+/// Credit lines and withdrawal caps of the clients.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Limits {
+    limits: HashMap<u16, ClientLimits>,
+
+    /// Limits of the clients without a row of their own
+    default: ClientLimits,
+}
+
+impl Limits {
+    /// Read a CSV table with a `client` column and any of the `limit`, `max_withdrawal`,
+    /// `daily_amount`, `daily_count` and `window_transactions` columns. A row with an empty
+    /// `client` holds the limits of the clients without a row of their own.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`LimitsError::InvalidLimit`] for a negative amount and
+    /// [`LimitsError::MissingWindow`] for daily caps without `window_transactions`, which
+    /// would leave withdrawals without timestamp uncapped.
+    pub fn from_reader<R: Read>(reader: R) -> Result<Self, LimitsError> {
+        let mut reader = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(reader);
+        let headers = reader.headers()?.clone();
+        let mut limits = Self::default();
+        for record in reader.records() {
+            let record = record?;
+            let row: LimitRow = record.deserialize(Some(&headers))?;
+            let line = record.position().map_or(0, |position| position.line());
+            let amounts = [row.limit, row.max_withdrawal, row.daily_amount];
+            if amounts
+                .into_iter()
+                .flatten()
+                .any(|amount| amount < Decimal::ZERO)
+            {
+                return Err(LimitsError::InvalidLimit(line));
+            }
+            if (row.daily_amount.is_some() || row.daily_count.is_some())
+                && row.window_transactions.is_none()
+            {
+                return Err(LimitsError::MissingWindow(line));
+            }
+            let client_limits = ClientLimits {
+                credit: row.limit.unwrap_or_default(),
+                velocity: VelocityLimits {
+                    max_withdrawal: row.max_withdrawal,
+                    daily_amount: row.daily_amount,
+                    daily_count: row.daily_count,
+                    window_transactions: row.window_transactions,
+                },
+            };
+            match row.client {
+                Some(client) => {
+                    limits.limits.insert(client, client_limits);
+                }
+                None => limits.default = client_limits,
+            }
+        }
+        Ok(limits)
+    }
+
+    /// Read the limits table in a file
+    pub fn load(path: &Path) -> Result<Self, LimitsError> {
+        Self::from_reader(BufReader::new(File::open(path)?))
+    }
+
+    /// Limits of a client, falling back to the default ones
+    pub fn get(&self, client: u16) -> ClientLimits {
+        self.limits.get(&client).copied().unwrap_or(self.default)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limits_are_read_from_csv() {
+        let limits = Limits::from_reader("client,limit\n1, 500\n2,0.5\n".as_bytes()).unwrap();
+        assert_eq!(limits.get(1).credit, Decimal::from(500));
+        assert_eq!(limits.get(2).credit, Decimal::new(5, 1));
+        assert_eq!(limits.get(3), ClientLimits::default());
+    }
+
+    #[test]
+    fn velocity_columns_are_optional_and_default_row_applies_to_others() {
+        let table = "client,max_withdrawal,daily_amount,daily_count,window_transactions\n\
+                     ,100,,3,20\n\
+                     1,,250,,10\n";
+        let limits = Limits::from_reader(table.as_bytes()).unwrap();
+        assert_eq!(
+            limits.get(1).velocity,
+            VelocityLimits {
+                daily_amount: Some(Decimal::from(250)),
+                window_transactions: Some(10),
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            limits.get(7),
+            ClientLimits {
+                credit: Decimal::ZERO,
+                velocity: VelocityLimits {
+                    max_withdrawal: Some(Decimal::from(100)),
+                    daily_count: Some(3),
+                    window_transactions: Some(20),
+                    ..Default::default()
+                },
+            }
+        );
+    }
+
+    #[test]
+    fn negative_limits_are_rejected() {
+        assert!(matches!(
+            Limits::from_reader("client,limit\n4,-1\n".as_bytes()),
+            Err(LimitsError::InvalidLimit(2))
+        ));
+        assert!(matches!(
+            Limits::from_reader(
+                "client,daily_amount,window_transactions\n4,1,5\n5,-1,5\n".as_bytes()
+            ),
+            Err(LimitsError::InvalidLimit(3))
+        ));
+        assert!(matches!(
+            Limits::from_reader("client,limit\nfour,1\n".as_bytes()),
+            Err(LimitsError::Csv(_))
+        ));
+    }
+
+    #[test]
+    fn daily_caps_without_window_transactions_are_rejected() {
+        assert!(matches!(
+            Limits::from_reader("client,daily_count\n,1\n".as_bytes()),
+            Err(LimitsError::MissingWindow(2))
+        ));
+        assert!(matches!(
+            Limits::from_reader("client,max_withdrawal,daily_amount\n4,10,\n5,,10\n".as_bytes()),
+            Err(LimitsError::MissingWindow(3))
+        ));
+    }
+
+    #[test]
+    fn window_is_a_day_or_a_number_of_transactions() {
+        let limits = VelocityLimits {
+            daily_count: Some(1),
+            window_transactions: Some(3),
+            ..Default::default()
+        };
+        let entry = WithdrawalEntry {
+            sequence: 5,
+            timestamp: Some(1_000),
+            currency: None,
+            amount: Decimal::ONE,
+        };
+        assert!(limits.in_window(&entry, Some(1_000 + 86_399), 100));
+        assert!(!limits.in_window(&entry, Some(1_000 + 86_400), 6));
+        assert!(limits.in_window(&entry, None, 7));
+        assert!(!limits.in_window(&entry, None, 8));
+
+        let untimestamped = WithdrawalEntry {
+            timestamp: None,
+            ..entry.clone()
+        };
+        assert!(limits.in_window(&untimestamped, Some(u64::MAX), 7));
+        assert!(!limits.in_window(&untimestamped, Some(1_000), 8));
+
+        assert!(!limits.has_expired(&entry, Some(1_000 + 86_400), 7));
+        assert!(!limits.has_expired(&entry, Some(1_000), 8));
+        assert!(limits.has_expired(&entry, Some(1_000 + 86_400), 8));
+    }
+}
@@ -1,75 +1,461 @@
-use clap::Parser;
-use model::{Account, Transaction};
-use std::{collections::HashMap, path::PathBuf};
+use clap::{Args, Parser, Subcommand};
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::PathBuf,
+    sync::Arc,
+};
+use tx_cli::{
+    Ledger,
+    input::{self, InputError, InputFormat, Precision, PrecisionPolicy, TransactionReader},
+    journal::{Journal, JournalError, Position, Resume},
+    limits::{Limits, LimitsError},
+    model::{Account, AccountError},
+    output::{
+        AccountRecord, CurrencyRecord, ErrorRecord, Format, MultiCurrencyRecord, OutputError,
+        RecordWriter, StatementRecord,
+    },
+    policy::{DisputePolicy, DisputeWindow, Policy},
+    report::{Rates, Report, ReportError, Rounding},
+    shard::ShardedLedger,
+    state::StateError,
+    store::{DiskFile, Storage, StoreError},
+};
 
-use crate::model::AccountError;
-
-mod model;
-mod rules;
 /// Transaction CLI tool
 #[derive(Parser)]
-#[command(version, about, long_about = None)]
+#[command(version, about, long_about = None, args_conflicts_with_subcommands = true)]
 struct Cli {
-    input: PathBuf,
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[command(flatten)]
+    options: Options,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Process the inputs and print the running balance statement of a single client
+    Statement {
+        /// Client whose statement is printed
+        #[arg(long)]
+        client: u16,
+
+        #[command(flatten)]
+        options: Options,
+    },
+}
+
+/// How transactions are read and processed, and where the results go
+#[derive(Args)]
+struct Options {
+    /// Input files processed in order; `-` or no file at all reads from stdin
+    inputs: Vec<PathBuf>,
+
+    /// Format of the input files, inferred from their extension when omitted
+    #[arg(short, long, value_enum)]
+    input_format: Option<InputFormat>,
+
+    /// What to do with amounts given with more decimal places than `--precision`
+    #[arg(long, value_enum, default_value_t = PrecisionPolicy::Truncate)]
+    precision_policy: PrecisionPolicy,
+
+    /// Number of decimal places amounts are kept with
+    #[arg(long, default_value_t = Precision::DEFAULT_SCALE, value_parser = clap::value_parser!(u32).range(0..=28))]
+    precision: u32,
 
     /// Sort the output per account number ascending
     #[arg(short, long, default_value_t = false)]
     sort: bool,
+
+    /// Format of the account states written to stdout
+    #[arg(short, long, value_enum, default_value_t = Format::Csv)]
+    format: Format,
+
+    /// Which transactions can be disputed
+    #[arg(long, value_enum, default_value_t = DisputePolicy::DepositsOnly)]
+    dispute_policy: DisputePolicy,
+
+    /// How long a transaction can be disputed: `<n>` subsequent transactions of its account,
+    /// `<n>d` days by timestamp, or `<n>d,<m>` days with a fallback of `<m>` transactions for
+    /// the ones without timestamp; expired transactions are pruned from the store
+    #[arg(long)]
+    dispute_window: Option<DisputeWindow>,
+
+    /// Credit lines and withdrawal caps of the clients, a CSV file with a `client` column and
+    /// `limit`, `max_withdrawal`, `daily_amount`, `daily_count` or `window_transactions` ones
+    #[arg(long)]
+    limits: Option<PathBuf>,
+
+    /// Keep the transaction history in this scratch file instead of memory
+    #[arg(long)]
+    store: Option<PathBuf>,
+
+    /// Write malformed input rows to this CSV file instead of stderr
+    #[arg(long)]
+    rejects: Option<PathBuf>,
+
+    /// Resume from the ledger state in this file, if it exists, and save it back afterwards;
+    /// a statement only reads it
+    #[arg(long)]
+    state: Option<PathBuf>,
+
+    /// Record the history of every account and save it with `--state`, for the statements
+    /// of later runs
+    #[arg(long, requires = "state")]
+    history: bool,
+
+    /// Number of worker threads, each owning the accounts of a shard of clients
+    #[arg(short, long, default_value_t = 1, value_parser = clap::value_parser!(u16).range(1..))]
+    threads: u16,
+
+    /// Journal accepted transactions to this file and replay it on startup
+    #[arg(long, conflicts_with = "threads")]
+    journal: Option<PathBuf>,
+
+    /// Number of journal entries written between two fsyncs
+    #[arg(long, default_value_t = 1000, requires = "journal")]
+    journal_batch: usize,
+
+    /// Write rejected transactions to this file instead of stderr
+    #[arg(long)]
+    errors: Option<PathBuf>,
+
+    /// Format of the errors file, inferred from its extension when omitted
+    #[arg(long, value_enum, requires = "errors")]
+    errors_format: Option<Format>,
+
+    /// Write each client's total converted to the reporting currency to this file
+    #[arg(long, requires_all = ["rates", "report_currency"])]
+    report: Option<PathBuf>,
+
+    /// Exchange rates table used by the report, a CSV file with `currency,rate` columns
+    #[arg(long, requires = "report")]
+    rates: Option<PathBuf>,
+
+    /// Currency the report totals are converted to
+    #[arg(long, requires = "report")]
+    report_currency: Option<String>,
+
+    /// How report totals are rounded
+    #[arg(long, value_enum, default_value_t = Rounding::HalfEven, requires = "report")]
+    rounding: Rounding,
+
+    /// Decimal places of the report totals
+    #[arg(long, default_value_t = 2, value_parser = clap::value_parser!(u32).range(0..=28), requires = "report")]
+    report_scale: u32,
 }
 
 #[derive(Debug, thiserror::Error)]
+#[allow(clippy::enum_variant_names)]
 enum Error {
     #[error(transparent)]
     AccountError(#[from] AccountError),
 
     #[error(transparent)]
     CSVError(#[from] csv::Error),
+
+    #[error(transparent)]
+    InputError(#[from] InputError),
+
+    #[error(transparent)]
+    OutputError(#[from] OutputError),
+
+    #[error(transparent)]
+    StateError(#[from] StateError),
+
+    #[error(transparent)]
+    JournalError(#[from] JournalError),
+
+    #[error(transparent)]
+    StoreError(#[from] StoreError),
+
+    #[error(transparent)]
+    ReportError(#[from] ReportError),
+
+    #[error(transparent)]
+    LimitsError(#[from] LimitsError),
+
+    #[error(transparent)]
+    IOError(#[from] std::io::Error),
+}
+
+/// Malformed input row skipped during processing
+#[derive(Debug, serde::Serialize)]
+struct Reject {
+    input: String,
+    line: u64,
+    record: String,
+    error: String,
+}
+
+impl Reject {
+    /// Reject for a row of `input` that cannot be read, or the error back if it is fatal
+    fn new(input: &str, err: InputError) -> Result<Self, InputError> {
+        match err {
+            InputError::InvalidRow {
+                line,
+                record,
+                message,
+            } => Ok(Reject {
+                input: input.to_string(),
+                line,
+                record,
+                error: message,
+            }),
+            InputError::ExcessPrecision {
+                line, ref record, ..
+            } => Ok(Reject {
+                input: input.to_string(),
+                line,
+                record: record.clone(),
+                error: err.to_string(),
+            }),
+            err => Err(err),
+        }
+    }
+}
+
+/// Writes a rejected transaction to the errors file, or to stderr when there is none.
+fn report_error<W: Write>(
+    errors: Option<&mut RecordWriter<W>>,
+    record: ErrorRecord,
+) -> Result<(), Error> {
+    match errors {
+        Some(errors) => errors.write(&record)?,
+        // print to stderr so on stdout redirection (>) does not include the error
+        None => eprintln!("{}", record.message),
+    }
+    Ok(())
 }
 
 fn main() -> Result<(), Error> {
-    let cli = Cli::parse();
+    let (cli, statement) = match Cli::parse() {
+        Cli {
+            command: Some(Command::Statement { client, options }),
+            ..
+        } => (options, Some(client)),
+        Cli { options, .. } => (options, None),
+    };
 
-    if !cli.input.is_file() {
-        eprintln!("Error: '{}' is not a valid file", cli.input.display());
+    let inputs = if cli.inputs.is_empty() {
+        vec![PathBuf::from(input::STDIN)]
+    } else {
+        cli.inputs
+    };
+    if let Some(input) = inputs
+        .iter()
+        .find(|input| !input::is_stdin(input) && !input.is_file())
+    {
+        eprintln!("Error: '{}' is not a valid file", input.display());
         std::process::exit(1);
     }
 
-    let mut csv_reader = csv::ReaderBuilder::new()
-        .trim(csv::Trim::All)
-        .from_path(cli.input)
-        .expect("failed to read from CSV");
+    let policy = Policy {
+        disputes: cli.dispute_policy,
+        dispute_window: cli.dispute_window,
+    };
+    let storage = match &cli.store {
+        Some(path) => Storage::Disk(DiskFile::create(path)?),
+        None => Storage::Memory,
+    };
+    let mut ledger = match &cli.state {
+        Some(path) if path.exists() => Ledger::load(path, policy, storage)?,
+        _ => Ledger::with_storage(policy, storage),
+    };
+    if let Some(path) = &cli.limits {
+        ledger.set_limits(Limits::load(path)?);
+    }
+    if cli.history {
+        ledger.record_histories();
+    }
+    if let Some(client) = statement {
+        ledger.record_history(client);
+    }
+    let report = match (&cli.report, &cli.rates, &cli.report_currency) {
+        (Some(path), Some(rates), Some(currency)) => Some((
+            path,
+            Report {
+                rates: Rates::load(rates)?,
+                currency: currency.clone(),
+                rounding: cli.rounding,
+                scale: cli.report_scale,
+            },
+        )),
+        _ => None,
+    };
+    let names: Vec<String> = inputs
+        .iter()
+        .map(|input| input.display().to_string())
+        .collect();
+    let (mut journal, mut resume) = match &cli.journal {
+        Some(path) => {
+            let (journal, entries) = Journal::open(path, cli.journal_batch)?;
+            let resume = Resume::new(&entries, &names);
+            ledger.replay(entries)?;
+            // a statement leaves the journal as it found it, like the state
+            (statement.is_none().then_some(journal), resume)
+        }
+        None => (None, Resume::default()),
+    };
+    let mut rejects = match &cli.rejects {
+        Some(path) => Some(csv::Writer::from_path(path)?),
+        None => None,
+    };
+    let mut errors = match &cli.errors {
+        Some(path) => {
+            let format = cli
+                .errors_format
+                .or_else(|| Format::from_path(path))
+                .unwrap_or(Format::Csv);
+            Some(RecordWriter::new(
+                format,
+                BufWriter::new(File::create(path)?),
+            ))
+        }
+        None => None,
+    };
 
-    let mut accounts: HashMap<u16, Account> = HashMap::new();
+    let mut sharded = (cli.threads > 1)
+        .then(|| ShardedLedger::new(std::mem::take(&mut ledger), cli.threads.into()));
 
-    for tx in csv_reader.deserialize::<Transaction>() {
-        let tx = tx.expect("the transaction is not valid!");
+    for (input, name) in inputs.iter().zip(names) {
+        let input_format = cli
+            .input_format
+            .or_else(|| InputFormat::from_path(input))
+            .unwrap_or(InputFormat::Csv);
+        let precision = Precision {
+            policy: cli.precision_policy,
+            scale: cli.precision,
+        };
+        let reader =
+            TransactionReader::with_precision(input_format, input::open(input)?, precision);
+        let name: Arc<str> = Arc::from(name);
 
-        let account = accounts
-            .entry(tx.client)
-            .or_insert_with(|| Account::new(tx.client));
+        for tx in reader {
+            let (line, tx) = match tx {
+                Ok(tx) => tx,
+                Err(err) => {
+                    let message = format!("{name}: {err}");
+                    let reject = Reject::new(&name, err)?;
+                    match rejects.as_mut() {
+                        Some(rejects) => rejects.serialize(reject)?,
+                        None => eprintln!("{message}"),
+                    }
+                    continue;
+                }
+            };
 
-        if let Err(err) = account.process_transaction(tx) {
-            // print to stderr so on stdout redirection (>) does not include the error
-            eprintln!("{err}");
+            // already applied by the run the journal was left by
+            if resume.skips(&name, line) {
+                continue;
+            }
+
+            if let Some(sharded) = sharded.as_mut() {
+                sharded.apply(&name, line, tx);
+                for record in sharded.ready() {
+                    report_error(errors.as_mut(), record)?;
+                }
+                continue;
+            }
+
+            let result = ledger.apply(tx.clone());
+            if let Some(journal) = journal.as_mut() {
+                let position = Position {
+                    input: name.to_string(),
+                    line,
+                };
+                journal.record(&ledger, position, &tx, &result)?;
+            }
+            match result {
+                Ok(()) => {}
+                Err(AccountError::Store(err)) => return Err(err.into()),
+                Err(err) => {
+                    report_error(errors.as_mut(), ErrorRecord::new(&name, line, &tx, &err))?
+                }
+            }
+        }
+    }
+
+    if let Some(sharded) = sharded {
+        let (merged, rejected) = sharded.finish()?;
+        ledger = merged;
+        for record in rejected {
+            report_error(errors.as_mut(), record)?;
+        }
+    }
+
+    if let Some(errors) = errors {
+        errors.finish()?;
+    }
+
+    if let Some(mut rejects) = rejects {
+        rejects.flush().map_err(csv::Error::from)?;
+    }
+
+    if let Some(journal) = journal.as_mut() {
+        journal.sync()?;
+    }
+
+    if let Some(path) = cli.state.as_ref().filter(|_| statement.is_none()) {
+        ledger.save(path)?;
+        // the saved state covers every journaled entry
+        if let Some(journal) = journal.as_mut() {
+            journal.clear()?;
         }
     }
 
-    let mut csv_writer = csv::WriterBuilder::new().from_writer(std::io::stdout());
+    let mut writer = RecordWriter::new(cli.format, std::io::stdout().lock());
+    if let Some(client) = statement {
+        let Some(account) = ledger.account(client) else {
+            eprintln!("Error: client {client} has no account");
+            std::process::exit(1);
+        };
+        for record in StatementRecord::of(account) {
+            writer.write(&record)?;
+        }
+    }
+    let mut report = match report {
+        Some((path, report)) => {
+            let format = Format::from_path(path).unwrap_or(Format::Csv);
+            let writer = RecordWriter::new(format, BufWriter::new(File::create(path)?));
+            Some((report, writer))
+        }
+        None => None,
+    };
 
     // README:
     // We are collecting here just for the sake of sorting for comparison between the output
     // and the accounts.csv base file
     // This allocation however just allocates pointer references, it does not clone account values
-    let mut accounts: Vec<&Account> = accounts.values().collect();
+    let mut accounts: Vec<&Account> = ledger.accounts().collect();
     if cli.sort {
         accounts.sort_by_key(|account| account.client);
     };
 
-    accounts.iter().for_each(|account| {
-        csv_writer
-            .serialize(account)
-            .expect("failed to serialize account")
-    });
+    // single currency feeds keep the plain layout, one row or object per account
+    let multi_currency = accounts.iter().any(|account| account.has_currencies());
+    let credit = cli.limits.is_some();
+    for account in accounts {
+        // a statement replaces the account output
+        match (statement, multi_currency, cli.format) {
+            (Some(_), ..) => {}
+            (None, false, _) => writer.write(&AccountRecord::new(account, credit))?,
+            (None, true, Format::Csv) => {
+                for record in CurrencyRecord::of(account, credit) {
+                    writer.write(&record)?;
+                }
+            }
+            (None, true, _) => writer.write(&MultiCurrencyRecord::new(account, credit))?,
+        }
+        if let Some((report, writer)) = report.as_mut() {
+            writer.write(&report.record(account)?)?;
+        }
+    }
+    writer.finish()?;
+    if let Some((_, writer)) = report {
+        writer.finish()?;
+    }
 
     Ok(())
 }
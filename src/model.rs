@@ -1,5 +1,9 @@
 mod account;
+mod history;
+mod record;
 mod transaction;
 
 pub use account::*;
+pub use history::*;
+pub use record::*;
 pub use transaction::*;
@@ -1,10 +1,17 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
 
 use rust_decimal::Decimal;
-use serde::{Serialize, Serializer, ser::SerializeStruct};
 
-use super::{Transaction, TransactionType};
-use crate::rules::{self, RuleError};
+use super::{
+    HistoryEntry, HistoryType, Transaction, TransactionKind, TransactionRecord, TransactionState,
+    TransactionType,
+};
+use crate::{
+    limits::{ClientLimits, WithdrawalEntry},
+    policy::{Policy, WindowStart},
+    rules::{self, RuleError},
+    store::{StoreError, TransactionStore},
+};
 
 #[derive(Debug, thiserror::Error)]
 pub enum AccountError {
@@ -13,32 +20,84 @@ pub enum AccountError {
 
     #[error(transparent)]
     RuleViolation(#[from] RuleError),
+
+    #[error("transaction store failure: {0}")]
+    Store(#[from] StoreError),
+}
+
+impl AccountError {
+    /// Stable machine-readable identifier of the error
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::MismatchingAccounts(..) => "mismatching_accounts",
+            Self::RuleViolation(err) => err.code(),
+            Self::Store(_) => "store_failure",
+        }
+    }
+}
+
+/// Funds of an account in a single currency
+#[derive(Debug, Default, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Balance {
+    pub available: Decimal,
+    pub held: Decimal,
+}
+
+impl Balance {
+    /// Available + held amounts
+    pub fn total(&self) -> Decimal {
+        self.available + self.held
+    }
+}
+
+/// Change a transaction makes to an account, worked out before any of it is applied
+#[derive(Debug)]
+enum Change {
+    /// Stores a new deposit or withdrawal, leaving the given balance in its currency
+    Open {
+        kind: TransactionKind,
+        amount: Decimal,
+        balance: Balance,
+    },
+
+    /// Stores a transaction in its new dispute state, leaving the given balance in its
+    /// currency
+    Settle {
+        record: TransactionRecord,
+        balance: Balance,
+    },
 }
 
 #[derive(Debug, Default)]
 pub struct Account {
     pub client: u16,
-    pub available: Decimal,
-    pub held: Decimal,
+
+    /// Balances per currency; `None` holds the transactions without currency
+    pub balances: BTreeMap<Option<String>, Balance>,
     pub frozen: bool,
+    pub policy: Policy,
 
-    pub(crate) deposits: HashMap<u32, Decimal>,
-    pub(crate) disputes: HashSet<u32>,
-}
+    /// Credit line and withdrawal caps of the client
+    pub limits: ClientLimits,
 
-impl Serialize for Account {
-    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
-    where
-        S: Serializer,
-    {
-        let mut state = serializer.serialize_struct("Account", 5)?;
-        state.serialize_field("client", &self.client)?;
-        state.serialize_field("available", &self.available.normalize().to_string())?;
-        state.serialize_field("held", &self.held.normalize().to_string())?;
-        state.serialize_field("total", &self.total().normalize().to_string())?;
-        state.serialize_field("locked", &self.frozen)?;
-        state.end()
-    }
+    pub(crate) transactions: Box<dyn TransactionStore>,
+
+    /// Number of transactions accepted by the account so far
+    pub(crate) sequence: u64,
+
+    /// Latest timestamp of the transactions accepted by the account
+    pub(crate) clock: Option<u64>,
+
+    /// Stored transactions in the order they leave the dispute window, with the point the
+    /// window is measured from
+    pub(crate) expiry: BTreeSet<(WindowStart, u32)>,
+
+    /// Withdrawals still within the velocity window, oldest first
+    pub(crate) withdrawals: VecDeque<WithdrawalEntry>,
+
+    /// Every transaction applied to the account since [`Account::record_history`], in order,
+    /// after the balances it already had then
+    pub(crate) history: Option<Vec<HistoryEntry>>,
 }
 
 impl Account {
@@ -49,89 +108,390 @@ impl Account {
         }
     }
 
-    /// Account available + held amounts
-    pub fn total(&self) -> Decimal {
-        self.available + self.held
+    pub fn with_policy(client: u16, policy: Policy) -> Self {
+        Self {
+            client,
+            policy,
+            ..Default::default()
+        }
     }
 
-    /// Return deposit amount if found
-    pub fn find_deposit(&self, tx_id: &u32) -> Option<&Decimal> {
-        self.deposits.get(tx_id)
+    /// Balance in the given currency, zero if the account never used it
+    pub fn balance(&self, currency: Option<&str>) -> Balance {
+        self.balances
+            .get(&currency.map(str::to_string))
+            .copied()
+            .unwrap_or_default()
     }
 
-    /// Return dispute transaction when found
-    pub fn has_dispute(&self, tx_id: &u32) -> Option<&u32> {
-        self.disputes.get(tx_id)
+    /// Balance in the given currency, opened on first use
+    pub(crate) fn balance_mut(&mut self, currency: Option<&str>) -> &mut Balance {
+        self.balances
+            .entry(currency.map(str::to_string))
+            .or_default()
     }
 
-    /// Increases the available balance by the given amount.
-    fn deposit(&mut self, tx: &Transaction) -> Result<(), RuleError> {
-        let amount = rules::require_amount(tx.tx, tx.amount)?;
-        self.available += amount;
-        self.deposits.insert(tx.tx, amount);
-        Ok(())
+    /// Balances per currency sorted by currency, with a zero balance for an account that
+    /// never accepted a transaction
+    pub fn currency_balances(&self) -> Vec<(Option<&str>, Balance)> {
+        if self.balances.is_empty() {
+            return vec![(None, Balance::default())];
+        }
+        self.balances
+            .iter()
+            .map(|(currency, balance)| (currency.as_deref(), *balance))
+            .collect()
     }
 
-    /// Decreases the available balance by the given amount.
-    fn withdrawal(&mut self, tx: &Transaction) -> Result<(), RuleError> {
-        let amount = rules::require_amount(tx.tx, tx.amount)?;
-        rules::check_sufficient_funds(self, amount)?;
-        self.available -= amount;
-        Ok(())
+    /// Credit still available in the given currency: the credit limit less the overdrawn
+    /// amount, if any
+    pub fn remaining_credit(&self, currency: Option<&str>) -> Decimal {
+        let overdrawn = -self.balance(currency).available.min(Decimal::ZERO);
+        (self.limits.credit - overdrawn).max(Decimal::ZERO)
+    }
+
+    /// Transactions applied to the account in order, each with the balance it left
+    pub fn history(&self) -> &[HistoryEntry] {
+        self.history.as_deref().unwrap_or_default()
+    }
+
+    /// Start recording the transactions applied to the account for [`Account::history`].
+    ///
+    /// The history grows with every transaction, so it is only kept when asked for. An account
+    /// that already applied transactions opens its history with one opening entry per balance.
+    pub fn record_history(&mut self) {
+        if self.history.is_some() {
+            return;
+        }
+        let opening = if self.sequence > 0 {
+            self.currency_balances()
+        } else {
+            Vec::new()
+        };
+        self.history = Some(
+            opening
+                .into_iter()
+                .map(|(currency, balance)| HistoryEntry {
+                    sequence: self.sequence,
+                    tx: None,
+                    r#type: HistoryType::Opening,
+                    amount: balance.available + balance.held,
+                    timestamp: None,
+                    currency: currency.map(str::to_string),
+                    available: balance.available,
+                    held: balance.held,
+                })
+                .collect(),
+        );
+    }
+
+    /// Whether any transaction of the account carried a currency
+    pub fn has_currencies(&self) -> bool {
+        self.balances.keys().any(Option::is_some)
+    }
+
+    /// Account opened with the given policy, keeping its transactions in `store`
+    pub fn with_store(client: u16, policy: Policy, store: Box<dyn TransactionStore>) -> Self {
+        Self {
+            client,
+            policy,
+            transactions: store,
+            ..Default::default()
+        }
+    }
+
+    /// Return transaction if found and disputable under the account dispute policy
+    pub fn find_transaction(&self, tx_id: &u32) -> Result<Option<TransactionRecord>, StoreError> {
+        Ok(self
+            .transactions
+            .get(*tx_id)?
+            .filter(|record| self.policy.disputes.allows(record.kind)))
+    }
+
+    /// Whether the transaction is currently under dispute
+    pub fn has_dispute(&self, tx_id: &u32) -> Result<bool, StoreError> {
+        Ok(self
+            .find_transaction(tx_id)?
+            .is_some_and(|record| record.state == TransactionState::Disputed))
+    }
+
+    /// Number and total amount of the withdrawals in `currency` within the velocity window of
+    /// a new withdrawal at `timestamp`
+    pub(crate) fn recent_withdrawals(
+        &self,
+        timestamp: Option<u64>,
+        currency: Option<&str>,
+    ) -> (u32, Decimal) {
+        let position = self.sequence + 1;
+        self.withdrawals
+            .iter()
+            .filter(|entry| entry.currency.as_deref() == currency)
+            .filter(|entry| self.limits.velocity.in_window(entry, timestamp, position))
+            .fold((0, Decimal::ZERO), |(count, total), entry| {
+                (count + 1, total.saturating_add(entry.amount))
+            })
+    }
+
+    /// Remembers a new withdrawal for the velocity window, forgetting the ones that left it.
+    fn record_withdrawal(&mut self, tx: &Transaction, amount: Decimal) {
+        let velocity = self.limits.velocity;
+        if !velocity.has_window() {
+            return;
+        }
+        let position = self.sequence + 1;
+        let clock = self.clock.max(tx.timestamp);
+        while self
+            .withdrawals
+            .front()
+            .is_some_and(|entry| velocity.has_expired(entry, clock, position))
+        {
+            self.withdrawals.pop_front();
+        }
+        self.withdrawals.push_back(WithdrawalEntry {
+            sequence: position,
+            timestamp: tx.timestamp,
+            currency: tx.currency.clone(),
+            amount,
+        });
     }
 
-    /// Moves funds from available to held for a disputed transaction.
-    fn dispute(&mut self, tx: &Transaction) -> Result<(), RuleError> {
-        let amount = *rules::get_deposit_amount(self, &tx.tx)?;
-        self.available -= amount;
-        self.held += amount;
-        self.disputes.insert(tx.tx);
+    /// Stores a new transaction, tracking it for eviction when a dispute window is set.
+    fn store_transaction(
+        &mut self,
+        tx: &Transaction,
+        kind: TransactionKind,
+        amount: Decimal,
+    ) -> Result<(), StoreError> {
+        let mut record = TransactionRecord::from_transaction(kind, amount, tx);
+        record.sequence = self.sequence + 1;
+        let start = self
+            .policy
+            .dispute_window
+            .and_then(|window| window.start(&record));
+        self.transactions.insert(tx.tx, record)?;
+        if let Some(start) = start {
+            self.expiry.insert((start, tx.tx));
+        }
         Ok(())
     }
 
-    /// Moves funds from held back to available, resolving a dispute.
-    fn resolve(&mut self, tx: &Transaction) -> Result<(), RuleError> {
-        let amount = *rules::get_deposit_amount(self, &tx.tx)?;
-        rules::check_dispute_exists(self, &tx.tx)?;
-        self.held -= amount;
-        self.available += amount;
-        self.disputes.remove(&tx.tx);
+    /// Prunes the stored transactions that left the dispute window.
+    ///
+    /// Expired transactions are kept for one more window, so a late dispute is reported as
+    /// expired rather than unknown, and are then evicted from the store. Transactions under
+    /// dispute are kept and queued again once they are settled.
+    ///
+    /// A transaction leaves the queue only once it is removed from the store, so an eviction
+    /// cut short by a store failure is picked up again by the next one.
+    fn evict_expired(&mut self) -> Result<(), StoreError> {
+        let Some(window) = self.policy.dispute_window else {
+            return Ok(());
+        };
+        let (sequence, clock) = (self.sequence, self.clock);
+        // each kind of start is in order, so the expired ones lead both ranges
+        let first_timestamp = (WindowStart::Timestamp(0), 0);
+        let expired: Vec<_> = [
+            self.expiry.range(..first_timestamp),
+            self.expiry.range(first_timestamp..),
+        ]
+        .into_iter()
+        .flat_map(|range| {
+            range.take_while(|(start, _)| window.has_passed(*start, sequence, clock, 2))
+        })
+        .copied()
+        .collect();
+        for (start, tx_id) in expired {
+            if self
+                .transactions
+                .get(tx_id)?
+                .is_some_and(|record| record.state != TransactionState::Disputed)
+            {
+                self.transactions.remove(tx_id)?;
+            }
+            self.expiry.remove(&(start, tx_id));
+        }
         Ok(())
     }
 
-    /// Removes held funds and freezes the account permanently.
-    fn chargeback(&mut self, tx: &Transaction) -> Result<(), RuleError> {
-        let amount = *rules::get_deposit_amount(self, &tx.tx)?;
-        rules::check_dispute_exists(self, &tx.tx)?;
-        self.held -= amount;
-        self.frozen = true;
-        self.disputes.remove(&tx.tx);
+    /// Checks a deposit, which increases the available balance by its amount.
+    fn deposit(&self, tx: &Transaction) -> Result<Change, AccountError> {
+        let amount = rules::require_positive_amount(tx.tx, tx.amount)?;
+        let currency = tx.currency.as_deref();
+        let balance = rules::checked_balance(tx.tx, self.balance(currency), amount, Decimal::ZERO)?;
+        Ok(Change::Open {
+            kind: TransactionKind::Deposit,
+            amount,
+            balance,
+        })
+    }
+
+    /// Checks a withdrawal, which decreases the available balance by its amount.
+    fn withdrawal(&self, tx: &Transaction) -> Result<Change, AccountError> {
+        let amount = rules::require_positive_amount(tx.tx, tx.amount)?;
+        let currency = tx.currency.as_deref();
+        rules::check_max_withdrawal(self, tx, amount)?;
+        rules::check_withdrawal_velocity(self, tx, amount)?;
+        rules::check_sufficient_funds(self, currency, amount)?;
+        let balance =
+            rules::checked_balance(tx.tx, self.balance(currency), -amount, Decimal::ZERO)?;
+        Ok(Change::Open {
+            kind: TransactionKind::Withdrawal,
+            amount,
+            balance,
+        })
+    }
+
+    /// Moves a disputed transaction to its next state, applying the given changes to the
+    /// available and held funds of its original currency.
+    fn settle(
+        &self,
+        tx_id: u32,
+        mut record: TransactionRecord,
+        state: TransactionState,
+        available: Decimal,
+        held: Decimal,
+    ) -> Result<Change, AccountError> {
+        let balance = rules::checked_balance(
+            tx_id,
+            self.balance(record.currency.as_deref()),
+            available,
+            held,
+        )?;
+        record.state = state;
+        Ok(Change::Settle { record, balance })
+    }
+
+    /// Checks a dispute, which holds the funds of a transaction in its original currency.
+    ///
+    /// A disputed deposit moves its amount from available to held. A disputed withdrawal
+    /// holds its amount as a pending credit, leaving available untouched.
+    fn dispute(&self, tx: &Transaction) -> Result<Change, AccountError> {
+        let record = rules::require_transaction(tx.tx, self.find_transaction(&tx.tx)?)?;
+        rules::check_not_already_disputed(tx.tx, &record)?;
+        rules::check_dispute_window(self, tx, &record)?;
+        let amount = record.amount;
+        let available = match record.kind {
+            TransactionKind::Deposit => -amount,
+            TransactionKind::Withdrawal => Decimal::ZERO,
+        };
+        self.settle(tx.tx, record, TransactionState::Disputed, available, amount)
+    }
+
+    /// Checks a resolve, which releases held funds, letting the disputed transaction stand.
+    ///
+    /// A resolved deposit moves its amount back to available. A resolved withdrawal drops
+    /// its pending credit.
+    fn resolve(&self, tx: &Transaction) -> Result<Change, AccountError> {
+        let record = rules::require_transaction(tx.tx, self.find_transaction(&tx.tx)?)?;
+        rules::check_dispute_exists(tx.tx, &record)?;
+        let amount = record.amount;
+        let available = match record.kind {
+            TransactionKind::Deposit => amount,
+            TransactionKind::Withdrawal => Decimal::ZERO,
+        };
+        self.settle(
+            tx.tx,
+            record,
+            TransactionState::Resolved,
+            available,
+            -amount,
+        )
+    }
+
+    /// Checks a chargeback, which reverses the disputed transaction and freezes the account
+    /// permanently.
+    ///
+    /// A charged back deposit removes its held funds. A charged back withdrawal returns its
+    /// held amount to available.
+    fn chargeback(&self, tx: &Transaction) -> Result<Change, AccountError> {
+        let record = rules::require_transaction(tx.tx, self.find_transaction(&tx.tx)?)?;
+        rules::check_dispute_exists(tx.tx, &record)?;
+        let amount = record.amount;
+        let available = match record.kind {
+            TransactionKind::Deposit => Decimal::ZERO,
+            TransactionKind::Withdrawal => amount,
+        };
+        self.settle(
+            tx.tx,
+            record,
+            TransactionState::ChargedBack,
+            available,
+            -amount,
+        )
+    }
+
+    /// Applies a change checked by one of the handlers above. No rule is checked from here
+    /// on: only the transaction store can fail, and its single write comes before any other
+    /// change, so a failing store leaves the account as it was.
+    fn apply(&mut self, tx: &Transaction, change: Change) -> Result<(), StoreError> {
+        let (amount, currency, balance) = match change {
+            Change::Open {
+                kind,
+                amount,
+                balance,
+            } => {
+                self.store_transaction(tx, kind, amount)?;
+                if kind == TransactionKind::Withdrawal {
+                    self.record_withdrawal(tx, amount);
+                }
+                (amount, tx.currency.clone(), balance)
+            }
+            Change::Settle { record, balance } => {
+                let (amount, currency) = (record.amount, record.currency.clone());
+                let frozen = record.state == TransactionState::ChargedBack;
+                // a settled transaction may have outlived its window while under dispute
+                let start = self
+                    .policy
+                    .dispute_window
+                    .filter(|_| record.state != TransactionState::Disputed)
+                    .and_then(|window| window.start(&record));
+                self.transactions.insert(tx.tx, record)?;
+                if let Some(start) = start {
+                    self.expiry.insert((start, tx.tx));
+                }
+                self.frozen |= frozen;
+                (amount, currency, balance)
+            }
+        };
+        *self.balance_mut(currency.as_deref()) = balance;
+        self.sequence += 1;
+        self.clock = self.clock.max(tx.timestamp);
+        if let Some(history) = self.history.as_mut() {
+            history.push(HistoryEntry {
+                sequence: self.sequence,
+                tx: Some(tx.tx),
+                r#type: HistoryType::Transaction(tx.r#type),
+                amount,
+                timestamp: tx.timestamp,
+                currency,
+                available: balance.available,
+                held: balance.held,
+            });
+        }
+        // eviction is housekeeping that does not belong to this transaction: whatever it
+        // could not remove stays queued for the next one
+        let _ = self.evict_expired();
         Ok(())
     }
 
+    /// Applies a transaction to the account.
+    ///
+    /// Every rule is checked against an untouched account before any change is made, so a
+    /// rejected transaction leaves the account exactly as it was.
     pub fn process_transaction(&mut self, tx: Transaction) -> Result<(), AccountError> {
         if self.client != tx.client {
             return Err(AccountError::MismatchingAccounts(self.client, tx.client));
         }
         rules::check_not_frozen(self)?;
 
-        match &tx.r#type {
-            TransactionType::Deposit => {
-                self.deposit(&tx)?;
-            }
-            TransactionType::Withdrawal => {
-                self.withdrawal(&tx)?;
-            }
-            TransactionType::Dispute => {
-                self.dispute(&tx)?;
-            }
-            TransactionType::Resolve => {
-                self.resolve(&tx)?;
-            }
-            TransactionType::Chargeback => {
-                self.chargeback(&tx)?;
-            }
-        }
+        let change = match &tx.r#type {
+            TransactionType::Deposit => self.deposit(&tx)?,
+            TransactionType::Withdrawal => self.withdrawal(&tx)?,
+            TransactionType::Dispute => self.dispute(&tx)?,
+            TransactionType::Resolve => self.resolve(&tx)?,
+            TransactionType::Chargeback => self.chargeback(&tx)?,
+        };
+        self.apply(&tx, change)?;
         Ok(())
     }
 }
@@ -140,38 +500,29 @@ impl Account {
 mod tests {
     use super::*;
 
-    fn make_tx(
-        r#type: TransactionType,
-        client: u16,
-        tx: u32,
-        amount: Option<Decimal>,
-    ) -> Transaction {
-        Transaction {
-            r#type,
-            client,
-            tx,
-            amount,
-        }
-    }
-
     fn make_deposit(client: u16, tx: u32, amount: Decimal) -> Transaction {
-        make_tx(TransactionType::Deposit, client, tx, Some(amount))
+        Transaction::new(TransactionType::Deposit, client, tx, Some(amount))
     }
 
     fn make_withdrawal(client: u16, tx: u32, amount: Decimal) -> Transaction {
-        make_tx(TransactionType::Withdrawal, client, tx, Some(amount))
+        Transaction::new(TransactionType::Withdrawal, client, tx, Some(amount))
     }
 
     fn make_dispute(client: u16, tx: u32) -> Transaction {
-        make_tx(TransactionType::Dispute, client, tx, None)
+        Transaction::new(TransactionType::Dispute, client, tx, None)
     }
 
     fn make_resolve(client: u16, tx: u32) -> Transaction {
-        make_tx(TransactionType::Resolve, client, tx, None)
+        Transaction::new(TransactionType::Resolve, client, tx, None)
     }
 
     fn make_chargeback(client: u16, tx: u32) -> Transaction {
-        make_tx(TransactionType::Chargeback, client, tx, None)
+        Transaction::new(TransactionType::Chargeback, client, tx, None)
+    }
+
+    fn at(mut tx: Transaction, timestamp: u64) -> Transaction {
+        tx.timestamp = Some(timestamp);
+        tx
     }
 
     mod deposit_tests {
@@ -183,7 +534,7 @@ mod tests {
             account
                 .process_transaction(make_deposit(1, 1, Decimal::from(100)))
                 .unwrap();
-            assert_eq!(account.available, Decimal::from(100));
+            assert_eq!(account.balance(None).available, Decimal::from(100));
         }
 
         #[test]
@@ -192,7 +543,7 @@ mod tests {
             account
                 .process_transaction(make_deposit(1, 1, Decimal::from(50)))
                 .unwrap();
-            assert_eq!(account.held, Decimal::ZERO);
+            assert_eq!(account.balance(None).held, Decimal::ZERO);
         }
 
         #[test]
@@ -201,7 +552,10 @@ mod tests {
             account
                 .process_transaction(make_deposit(1, 1, Decimal::from(75)))
                 .unwrap();
-            assert_eq!(account.total(), account.available);
+            assert_eq!(
+                account.balance(None).total(),
+                account.balance(None).available
+            );
         }
 
         #[test]
@@ -216,7 +570,7 @@ mod tests {
             account
                 .process_transaction(make_deposit(1, 3, Decimal::from(3)))
                 .unwrap();
-            assert_eq!(account.available, Decimal::from(6));
+            assert_eq!(account.balance(None).available, Decimal::from(6));
         }
 
         #[test]
@@ -228,19 +582,33 @@ mod tests {
                 result,
                 Err(AccountError::RuleViolation(RuleError::AccountFrozen))
             ));
-            assert_eq!(account.available, Decimal::ZERO);
+            assert_eq!(account.balance(None).available, Decimal::ZERO);
+        }
+
+        #[test]
+        fn deposit_non_positive_amount_returns_error() {
+            let mut account = Account::new(1);
+            for (tx, amount) in [(1, Decimal::from(-50)), (2, Decimal::ZERO)] {
+                let result = account.process_transaction(make_deposit(1, tx, amount));
+                assert!(matches!(
+                    result,
+                    Err(AccountError::RuleViolation(RuleError::NonPositiveAmount(id))) if id == tx
+                ));
+            }
+            assert_eq!(account.balance(None), Balance::default());
+            assert!(account.transactions.is_empty());
         }
 
         #[test]
         fn deposit_missing_amount_returns_error() {
             let mut account = Account::new(1);
             let result =
-                account.process_transaction(make_tx(TransactionType::Deposit, 1, 1, None));
+                account.process_transaction(Transaction::new(TransactionType::Deposit, 1, 1, None));
             assert!(matches!(
                 result,
                 Err(AccountError::RuleViolation(RuleError::MissingAmount(1)))
             ));
-            assert_eq!(account.available, Decimal::ZERO);
+            assert_eq!(account.balance(None).available, Decimal::ZERO);
         }
     }
 
@@ -250,60 +618,237 @@ mod tests {
         #[test]
         fn withdrawal_decreases_available() {
             let mut account = Account::new(1);
-            account.available = Decimal::from(100);
+            account.balance_mut(None).available = Decimal::from(100);
             account
                 .process_transaction(make_withdrawal(1, 1, Decimal::from(40)))
                 .unwrap();
-            assert_eq!(account.available, Decimal::from(60));
-            assert_eq!(account.held, Decimal::ZERO);
+            assert_eq!(account.balance(None).available, Decimal::from(60));
+            assert_eq!(account.balance(None).held, Decimal::ZERO);
         }
 
         #[test]
         fn withdrawal_exact_balance_succeeds() {
             let mut account = Account::new(1);
-            account.available = Decimal::from(50);
+            account.balance_mut(None).available = Decimal::from(50);
             account
                 .process_transaction(make_withdrawal(1, 1, Decimal::from(50)))
                 .unwrap();
-            assert_eq!(account.available, Decimal::ZERO);
+            assert_eq!(account.balance(None).available, Decimal::ZERO);
         }
 
         #[test]
         fn withdrawal_insufficient_funds_returns_error_and_does_not_modify_account() {
             let mut account = Account::new(1);
-            account.available = Decimal::from(10);
+            account.balance_mut(None).available = Decimal::from(10);
             let result = account.process_transaction(make_withdrawal(1, 1, Decimal::from(20)));
             assert!(matches!(
                 result,
                 Err(AccountError::RuleViolation(RuleError::InsuficientFunds))
             ));
-            assert_eq!(account.available, Decimal::from(10));
+            assert_eq!(account.balance(None).available, Decimal::from(10));
         }
 
         #[test]
         fn withdrawal_on_frozen_account_returns_error() {
             let mut account = Account::new(1);
-            account.available = Decimal::from(100);
+            account.balance_mut(None).available = Decimal::from(100);
             account.frozen = true;
             let result = account.process_transaction(make_withdrawal(1, 1, Decimal::from(40)));
             assert!(matches!(
                 result,
                 Err(AccountError::RuleViolation(RuleError::AccountFrozen))
             ));
-            assert_eq!(account.available, Decimal::from(100));
+            assert_eq!(account.balance(None).available, Decimal::from(100));
+        }
+
+        #[test]
+        fn negative_withdrawal_returns_error() {
+            let mut account = Account::new(1);
+            let result = account.process_transaction(make_withdrawal(1, 1, Decimal::from(-50)));
+            assert!(matches!(
+                result,
+                Err(AccountError::RuleViolation(RuleError::NonPositiveAmount(1)))
+            ));
+            assert_eq!(account.balance(None), Balance::default());
         }
 
         #[test]
         fn withdrawal_missing_amount_returns_error() {
             let mut account = Account::new(1);
-            account.available = Decimal::from(100);
-            let result =
-                account.process_transaction(make_tx(TransactionType::Withdrawal, 1, 1, None));
+            account.balance_mut(None).available = Decimal::from(100);
+            let result = account.process_transaction(Transaction::new(
+                TransactionType::Withdrawal,
+                1,
+                1,
+                None,
+            ));
             assert!(matches!(
                 result,
                 Err(AccountError::RuleViolation(RuleError::MissingAmount(1)))
             ));
-            assert_eq!(account.available, Decimal::from(100));
+            assert_eq!(account.balance(None).available, Decimal::from(100));
+        }
+    }
+
+    mod credit_limit_tests {
+        use super::*;
+
+        #[test]
+        fn withdrawal_may_overdraw_down_to_the_limit() {
+            let mut account = Account::new(1);
+            account.limits.credit = Decimal::from(50);
+            account
+                .process_transaction(make_deposit(1, 1, Decimal::from(10)))
+                .unwrap();
+            account
+                .process_transaction(make_withdrawal(1, 2, Decimal::from(60)))
+                .unwrap();
+            assert_eq!(account.balance(None).available, Decimal::from(-50));
+            assert_eq!(account.remaining_credit(None), Decimal::ZERO);
+            let result = account.process_transaction(make_withdrawal(1, 3, Decimal::new(1, 4)));
+            assert!(matches!(
+                result,
+                Err(AccountError::RuleViolation(RuleError::InsuficientFunds))
+            ));
+            assert_eq!(account.balance(None).available, Decimal::from(-50));
+        }
+
+        #[test]
+        fn remaining_credit_is_the_limit_until_overdrawn() {
+            let mut account = Account::new(1);
+            account.limits.credit = Decimal::from(50);
+            account.balance_mut(None).available = Decimal::from(20);
+            assert_eq!(account.remaining_credit(None), Decimal::from(50));
+            account.balance_mut(None).available = Decimal::from(-20);
+            assert_eq!(account.remaining_credit(None), Decimal::from(30));
+            // a dispute may push the balance past the limit
+            account.balance_mut(None).available = Decimal::from(-80);
+            assert_eq!(account.remaining_credit(None), Decimal::ZERO);
+        }
+    }
+
+    mod velocity_tests {
+        use super::*;
+        use crate::limits::VelocityLimits;
+
+        fn account_with_velocity(velocity: VelocityLimits) -> Account {
+            let mut account = Account::new(1);
+            account.limits.velocity = velocity;
+            account.balance_mut(None).available = Decimal::from(1000);
+            account
+        }
+
+        #[test]
+        fn withdrawal_above_maximum_returns_error() {
+            let mut account = account_with_velocity(VelocityLimits {
+                max_withdrawal: Some(Decimal::from(100)),
+                ..Default::default()
+            });
+            account
+                .process_transaction(make_withdrawal(1, 1, Decimal::from(100)))
+                .unwrap();
+            let result = account.process_transaction(make_withdrawal(1, 2, Decimal::new(10001, 2)));
+            assert!(matches!(
+                result,
+                Err(AccountError::RuleViolation(
+                    RuleError::WithdrawalAboveMaximum(2)
+                ))
+            ));
+            assert_eq!(account.balance(None).available, Decimal::from(900));
+        }
+
+        #[test]
+        fn daily_amount_is_measured_over_a_day_of_timestamps() {
+            let mut account = account_with_velocity(VelocityLimits {
+                daily_amount: Some(Decimal::from(100)),
+                ..Default::default()
+            });
+            let day = 86_400;
+            account
+                .process_transaction(at(make_withdrawal(1, 1, Decimal::from(60)), 1_000))
+                .unwrap();
+            account
+                .process_transaction(at(make_withdrawal(1, 2, Decimal::from(40)), 2_000))
+                .unwrap();
+            let result = account
+                .process_transaction(at(make_withdrawal(1, 3, Decimal::ONE), 1_000 + day - 1));
+            assert!(matches!(
+                result,
+                Err(AccountError::RuleViolation(RuleError::DailyLimitExceeded(
+                    3
+                )))
+            ));
+            // the first withdrawal left the window
+            account
+                .process_transaction(at(make_withdrawal(1, 4, Decimal::from(60)), 1_000 + day))
+                .unwrap();
+            assert_eq!(account.balance(None).available, Decimal::from(840));
+        }
+
+        #[test]
+        fn withdrawal_count_falls_back_to_sequence_without_timestamps() {
+            let mut account = account_with_velocity(VelocityLimits {
+                daily_count: Some(2),
+                window_transactions: Some(3),
+                ..Default::default()
+            });
+            account
+                .process_transaction(make_withdrawal(1, 1, Decimal::ONE))
+                .unwrap();
+            account
+                .process_transaction(make_withdrawal(1, 2, Decimal::ONE))
+                .unwrap();
+            let result = account.process_transaction(make_withdrawal(1, 3, Decimal::ONE));
+            assert!(matches!(
+                result,
+                Err(AccountError::RuleViolation(RuleError::TooManyWithdrawals(
+                    3
+                )))
+            ));
+            // rejected withdrawals do not count, deposits move the window on
+            account
+                .process_transaction(make_deposit(1, 4, Decimal::ONE))
+                .unwrap();
+            account
+                .process_transaction(make_withdrawal(1, 5, Decimal::ONE))
+                .unwrap();
+            assert_eq!(account.withdrawals.len(), 2);
+        }
+
+        #[test]
+        fn caps_apply_per_currency() {
+            let mut account = account_with_velocity(VelocityLimits {
+                daily_count: Some(1),
+                window_transactions: Some(10),
+                ..Default::default()
+            });
+            account.balance_mut(Some("EUR")).available = Decimal::from(10);
+            account
+                .process_transaction(make_withdrawal(1, 1, Decimal::ONE))
+                .unwrap();
+            let mut withdrawal = make_withdrawal(1, 2, Decimal::ONE);
+            withdrawal.currency = Some("EUR".to_string());
+            account.process_transaction(withdrawal).unwrap();
+        }
+
+        #[test]
+        fn timestamped_withdrawal_counts_earlier_ones_without_timestamp() {
+            let mut account = account_with_velocity(VelocityLimits {
+                daily_count: Some(1),
+                window_transactions: Some(3),
+                ..Default::default()
+            });
+            account
+                .process_transaction(make_withdrawal(1, 1, Decimal::ONE))
+                .unwrap();
+            let result =
+                account.process_transaction(at(make_withdrawal(1, 2, Decimal::ONE), 1_000));
+            assert!(matches!(
+                result,
+                Err(AccountError::RuleViolation(RuleError::TooManyWithdrawals(
+                    2
+                )))
+            ));
         }
     }
 
@@ -316,11 +861,11 @@ mod tests {
             account
                 .process_transaction(make_deposit(1, 1, Decimal::from(100)))
                 .unwrap();
-            let total_before = account.total();
+            let total_before = account.balance(None).total();
             account.process_transaction(make_dispute(1, 1)).unwrap();
-            assert_eq!(account.available, Decimal::ZERO);
-            assert_eq!(account.held, Decimal::from(100));
-            assert_eq!(account.total(), total_before);
+            assert_eq!(account.balance(None).available, Decimal::ZERO);
+            assert_eq!(account.balance(None).held, Decimal::from(100));
+            assert_eq!(account.balance(None).total(), total_before);
         }
 
         #[test]
@@ -334,8 +879,63 @@ mod tests {
                 result,
                 Err(AccountError::RuleViolation(RuleError::DepositNotFound(99)))
             ));
-            assert_eq!(account.available, Decimal::from(100));
-            assert_eq!(account.held, Decimal::ZERO);
+            assert_eq!(account.balance(None).available, Decimal::from(100));
+            assert_eq!(account.balance(None).held, Decimal::ZERO);
+        }
+
+        #[test]
+        fn dispute_twice_returns_error_and_does_not_modify_account() {
+            let mut account = Account::new(1);
+            account
+                .process_transaction(make_deposit(1, 1, Decimal::from(100)))
+                .unwrap();
+            account.process_transaction(make_dispute(1, 1)).unwrap();
+            let result = account.process_transaction(make_dispute(1, 1));
+            assert!(matches!(
+                result,
+                Err(AccountError::RuleViolation(
+                    RuleError::TransactionAlreadyDisputed(1)
+                ))
+            ));
+            assert_eq!(account.balance(None).available, Decimal::ZERO);
+            assert_eq!(account.balance(None).held, Decimal::from(100));
+        }
+
+        #[test]
+        fn dispute_after_resolve_returns_error() {
+            let mut account = Account::new(1);
+            account
+                .process_transaction(make_deposit(1, 1, Decimal::from(100)))
+                .unwrap();
+            account.process_transaction(make_dispute(1, 1)).unwrap();
+            account.process_transaction(make_resolve(1, 1)).unwrap();
+            let result = account.process_transaction(make_dispute(1, 1));
+            assert!(matches!(
+                result,
+                Err(AccountError::RuleViolation(
+                    RuleError::TransactionAlreadyDisputed(1)
+                ))
+            ));
+            assert_eq!(account.balance(None).available, Decimal::from(100));
+            assert_eq!(account.balance(None).held, Decimal::ZERO);
+            assert_eq!(
+                account.find_transaction(&1).unwrap().unwrap().state,
+                TransactionState::Resolved
+            );
+        }
+
+        #[test]
+        fn dispute_marks_deposit_as_disputed() {
+            let mut account = Account::new(1);
+            account
+                .process_transaction(make_deposit(1, 1, Decimal::from(100)))
+                .unwrap();
+            account.process_transaction(make_dispute(1, 1)).unwrap();
+            assert!(account.has_dispute(&1).unwrap());
+            assert_eq!(
+                account.find_transaction(&1).unwrap().unwrap().state,
+                TransactionState::Disputed
+            );
         }
 
         #[test]
@@ -350,8 +950,8 @@ mod tests {
                 result,
                 Err(AccountError::RuleViolation(RuleError::AccountFrozen))
             ));
-            assert_eq!(account.available, Decimal::from(100));
-            assert_eq!(account.held, Decimal::ZERO);
+            assert_eq!(account.balance(None).available, Decimal::from(100));
+            assert_eq!(account.balance(None).held, Decimal::ZERO);
         }
     }
 
@@ -365,11 +965,11 @@ mod tests {
                 .process_transaction(make_deposit(1, 1, Decimal::from(100)))
                 .unwrap();
             account.process_transaction(make_dispute(1, 1)).unwrap();
-            let total_before = account.total();
+            let total_before = account.balance(None).total();
             account.process_transaction(make_resolve(1, 1)).unwrap();
-            assert_eq!(account.available, Decimal::from(100));
-            assert_eq!(account.held, Decimal::ZERO);
-            assert_eq!(account.total(), total_before);
+            assert_eq!(account.balance(None).available, Decimal::from(100));
+            assert_eq!(account.balance(None).held, Decimal::ZERO);
+            assert_eq!(account.balance(None).total(), total_before);
         }
 
         #[test]
@@ -385,8 +985,27 @@ mod tests {
                     RuleError::TrasactionNotOnDispute(1)
                 ))
             ));
-            assert_eq!(account.available, Decimal::from(100));
-            assert_eq!(account.held, Decimal::ZERO);
+            assert_eq!(account.balance(None).available, Decimal::from(100));
+            assert_eq!(account.balance(None).held, Decimal::ZERO);
+        }
+
+        #[test]
+        fn resolve_twice_returns_error_and_does_not_modify_account() {
+            let mut account = Account::new(1);
+            account
+                .process_transaction(make_deposit(1, 1, Decimal::from(100)))
+                .unwrap();
+            account.process_transaction(make_dispute(1, 1)).unwrap();
+            account.process_transaction(make_resolve(1, 1)).unwrap();
+            let result = account.process_transaction(make_resolve(1, 1));
+            assert!(matches!(
+                result,
+                Err(AccountError::RuleViolation(
+                    RuleError::TrasactionNotOnDispute(1)
+                ))
+            ));
+            assert_eq!(account.balance(None).available, Decimal::from(100));
+            assert_eq!(account.balance(None).held, Decimal::ZERO);
         }
 
         #[test]
@@ -416,8 +1035,8 @@ mod tests {
                 result,
                 Err(AccountError::RuleViolation(RuleError::AccountFrozen))
             ));
-            assert_eq!(account.held, Decimal::from(100));
-            assert_eq!(account.available, Decimal::ZERO);
+            assert_eq!(account.balance(None).held, Decimal::from(100));
+            assert_eq!(account.balance(None).available, Decimal::ZERO);
         }
     }
 
@@ -432,9 +1051,13 @@ mod tests {
                 .unwrap();
             account.process_transaction(make_dispute(1, 1)).unwrap();
             account.process_transaction(make_chargeback(1, 1)).unwrap();
-            assert_eq!(account.held, Decimal::ZERO);
-            assert_eq!(account.available, Decimal::ZERO);
+            assert_eq!(account.balance(None).held, Decimal::ZERO);
+            assert_eq!(account.balance(None).available, Decimal::ZERO);
             assert!(account.frozen);
+            assert_eq!(
+                account.find_transaction(&1).unwrap().unwrap().state,
+                TransactionState::ChargedBack
+            );
         }
 
         #[test]
@@ -444,9 +1067,12 @@ mod tests {
                 .process_transaction(make_deposit(1, 1, Decimal::from(100)))
                 .unwrap();
             account.process_transaction(make_dispute(1, 1)).unwrap();
-            let total_before = account.total();
+            let total_before = account.balance(None).total();
             account.process_transaction(make_chargeback(1, 1)).unwrap();
-            assert_eq!(account.total(), total_before - Decimal::from(100));
+            assert_eq!(
+                account.balance(None).total(),
+                total_before - Decimal::from(100)
+            );
             assert!(account.frozen);
         }
 
@@ -472,7 +1098,7 @@ mod tests {
                 .unwrap();
             account.process_transaction(make_dispute(1, 1)).unwrap();
             let _ = account.process_transaction(make_chargeback(1, 99));
-            assert_eq!(account.held, Decimal::from(100));
+            assert_eq!(account.balance(None).held, Decimal::from(100));
             assert!(!account.frozen);
         }
 
@@ -498,8 +1124,8 @@ mod tests {
                 .process_transaction(make_deposit(1, 1, Decimal::from(100)))
                 .unwrap();
             let _ = account.process_transaction(make_chargeback(1, 1));
-            assert_eq!(account.available, Decimal::from(100));
-            assert_eq!(account.held, Decimal::ZERO);
+            assert_eq!(account.balance(None).available, Decimal::from(100));
+            assert_eq!(account.balance(None).held, Decimal::ZERO);
             assert!(!account.frozen);
         }
 
@@ -516,22 +1142,1048 @@ mod tests {
                 result,
                 Err(AccountError::RuleViolation(RuleError::AccountFrozen))
             ));
-            assert_eq!(account.held, Decimal::from(100));
+            assert_eq!(account.balance(None).held, Decimal::from(100));
             assert!(account.frozen);
         }
     }
 
-    mod account_error_tests {
+    mod withdrawal_dispute_tests {
         use super::*;
+        use crate::policy::DisputePolicy;
+
+        fn account_with_withdrawal() -> Account {
+            let mut account = Account::with_policy(
+                1,
+                Policy {
+                    disputes: DisputePolicy::DepositsAndWithdrawals,
+                    ..Default::default()
+                },
+            );
+            account
+                .process_transaction(make_deposit(1, 1, Decimal::from(100)))
+                .unwrap();
+            account
+                .process_transaction(make_withdrawal(1, 2, Decimal::from(40)))
+                .unwrap();
+            account
+        }
 
         #[test]
-        fn mismatching_accounts_returns_error() {
+        fn withdrawal_dispute_rejected_by_default_policy() {
             let mut account = Account::new(1);
-            let result = account.process_transaction(make_deposit(2, 1, Decimal::from(100)));
+            account
+                .process_transaction(make_deposit(1, 1, Decimal::from(100)))
+                .unwrap();
+            account
+                .process_transaction(make_withdrawal(1, 2, Decimal::from(40)))
+                .unwrap();
+            let result = account.process_transaction(make_dispute(1, 2));
             assert!(matches!(
                 result,
-                Err(AccountError::MismatchingAccounts(1, 2))
+                Err(AccountError::RuleViolation(RuleError::DepositNotFound(2)))
             ));
+            assert_eq!(account.balance(None).available, Decimal::from(60));
+            assert_eq!(account.balance(None).held, Decimal::ZERO);
+        }
+
+        #[test]
+        fn dispute_holds_withdrawn_amount() {
+            let mut account = account_with_withdrawal();
+            account.process_transaction(make_dispute(1, 2)).unwrap();
+            assert_eq!(account.balance(None).available, Decimal::from(60));
+            assert_eq!(account.balance(None).held, Decimal::from(40));
+            assert_eq!(account.balance(None).total(), Decimal::from(100));
+        }
+
+        #[test]
+        fn resolve_releases_pending_credit() {
+            let mut account = account_with_withdrawal();
+            account.process_transaction(make_dispute(1, 2)).unwrap();
+            account.process_transaction(make_resolve(1, 2)).unwrap();
+            assert_eq!(account.balance(None).available, Decimal::from(60));
+            assert_eq!(account.balance(None).held, Decimal::ZERO);
+            assert!(!account.frozen);
+        }
+
+        #[test]
+        fn chargeback_returns_withdrawn_amount_and_freezes() {
+            let mut account = account_with_withdrawal();
+            account.process_transaction(make_dispute(1, 2)).unwrap();
+            account.process_transaction(make_chargeback(1, 2)).unwrap();
+            assert_eq!(account.balance(None).available, Decimal::from(100));
+            assert_eq!(account.balance(None).held, Decimal::ZERO);
+            assert!(account.frozen);
+        }
+
+        #[test]
+        fn withdrawal_dispute_twice_returns_error() {
+            let mut account = account_with_withdrawal();
+            account.process_transaction(make_dispute(1, 2)).unwrap();
+            let result = account.process_transaction(make_dispute(1, 2));
+            assert!(matches!(
+                result,
+                Err(AccountError::RuleViolation(
+                    RuleError::TransactionAlreadyDisputed(2)
+                ))
+            ));
+            assert_eq!(account.balance(None).held, Decimal::from(40));
+        }
+
+        #[test]
+        fn deposit_disputes_unchanged_under_withdrawal_policy() {
+            let mut account = account_with_withdrawal();
+            account.process_transaction(make_dispute(1, 1)).unwrap();
+            assert_eq!(account.balance(None).available, Decimal::from(-40));
+            assert_eq!(account.balance(None).held, Decimal::from(100));
+        }
+    }
+
+    mod dispute_window_tests {
+        use super::*;
+        use crate::policy::DisputeWindow;
+
+        const DAY: u64 = 86_400;
+
+        fn account_with_window(window: DisputeWindow) -> Account {
+            Account::with_policy(
+                1,
+                Policy {
+                    dispute_window: Some(window),
+                    ..Default::default()
+                },
+            )
+        }
+
+        #[test]
+        fn dispute_within_window_succeeds() {
+            let mut account = account_with_window(DisputeWindow::Transactions(2));
+            account
+                .process_transaction(make_deposit(1, 1, Decimal::from(100)))
+                .unwrap();
+            account
+                .process_transaction(make_deposit(1, 2, Decimal::from(5)))
+                .unwrap();
+            account.process_transaction(make_dispute(1, 1)).unwrap();
+            assert_eq!(account.balance(None).held, Decimal::from(100));
+        }
+
+        #[test]
+        fn dispute_after_window_returns_error() {
+            let mut account = account_with_window(DisputeWindow::Transactions(2));
+            for tx in 1..=3 {
+                account
+                    .process_transaction(make_deposit(1, tx, Decimal::from(10)))
+                    .unwrap();
+            }
+            let result = account.process_transaction(make_dispute(1, 1));
+            assert!(matches!(
+                result,
+                Err(AccountError::RuleViolation(
+                    RuleError::DisputeWindowExpired(1)
+                ))
+            ));
+            assert_eq!(account.balance(None).available, Decimal::from(30));
+            assert_eq!(account.balance(None).held, Decimal::ZERO);
+        }
+
+        #[test]
+        fn expired_transactions_are_pruned_after_another_window() {
+            let mut account = account_with_window(DisputeWindow::Transactions(1));
+            for tx in 1..=3 {
+                account
+                    .process_transaction(make_deposit(1, tx, Decimal::from(10)))
+                    .unwrap();
+            }
+            assert_eq!(account.transactions.len(), 2);
+            assert!(matches!(
+                account.process_transaction(make_dispute(1, 1)),
+                Err(AccountError::RuleViolation(RuleError::DepositNotFound(1)))
+            ));
+            assert!(matches!(
+                account.process_transaction(make_dispute(1, 2)),
+                Err(AccountError::RuleViolation(
+                    RuleError::DisputeWindowExpired(2)
+                ))
+            ));
+        }
+
+        #[test]
+        fn rejected_transactions_do_not_count() {
+            let mut account = account_with_window(DisputeWindow::Transactions(1));
+            account
+                .process_transaction(make_deposit(1, 1, Decimal::from(10)))
+                .unwrap();
+            assert!(
+                account
+                    .process_transaction(make_withdrawal(1, 2, Decimal::from(99)))
+                    .is_err()
+            );
+            account.process_transaction(make_dispute(1, 1)).unwrap();
+        }
+
+        #[test]
+        fn disputed_transaction_outlives_window() {
+            let mut account = account_with_window(DisputeWindow::Transactions(1));
+            account
+                .process_transaction(make_deposit(1, 1, Decimal::from(10)))
+                .unwrap();
+            account.process_transaction(make_dispute(1, 1)).unwrap();
+            for tx in 2..=4 {
+                account
+                    .process_transaction(make_deposit(1, tx, Decimal::from(5)))
+                    .unwrap();
+            }
+            account.process_transaction(make_resolve(1, 1)).unwrap();
+            assert_eq!(account.balance(None).available, Decimal::from(25));
+            assert_eq!(account.balance(None).held, Decimal::ZERO);
+        }
+
+        #[test]
+        fn settled_transaction_is_evicted_after_its_window() {
+            let mut account = account_with_window(DisputeWindow::Transactions(1));
+            account
+                .process_transaction(make_deposit(1, 1, Decimal::from(10)))
+                .unwrap();
+            account.process_transaction(make_dispute(1, 1)).unwrap();
+            for tx in 2..=4 {
+                account
+                    .process_transaction(make_deposit(1, tx, Decimal::from(5)))
+                    .unwrap();
+            }
+            account.process_transaction(make_resolve(1, 1)).unwrap();
+            assert_eq!(account.transactions.get(1).unwrap(), None);
+            assert!(!account.expiry.iter().any(|(_, tx_id)| *tx_id == 1));
+        }
+
+        #[test]
+        fn days_window_uses_timestamps() {
+            let mut account = account_with_window(DisputeWindow::Days(30));
+            account
+                .process_transaction(at(make_deposit(1, 1, Decimal::from(10)), 0))
+                .unwrap();
+            account
+                .process_transaction(at(make_deposit(1, 2, Decimal::from(10)), DAY))
+                .unwrap();
+            let result = account.process_transaction(at(make_dispute(1, 1), 30 * DAY));
+            assert!(matches!(
+                result,
+                Err(AccountError::RuleViolation(
+                    RuleError::DisputeWindowExpired(1)
+                ))
+            ));
+            account
+                .process_transaction(at(make_dispute(1, 2), 30 * DAY))
+                .unwrap();
+            assert_eq!(account.balance(None).held, Decimal::from(10));
+        }
+
+        #[test]
+        fn days_window_prunes_as_time_passes() {
+            let mut account = account_with_window(DisputeWindow::Days(1));
+            account
+                .process_transaction(at(make_deposit(1, 1, Decimal::from(10)), 0))
+                .unwrap();
+            account
+                .process_transaction(at(make_deposit(1, 2, Decimal::from(10)), 2 * DAY))
+                .unwrap();
+            assert_eq!(account.transactions.len(), 1);
+            assert_eq!(account.expiry.len(), 1);
+        }
+
+        #[test]
+        fn days_window_keeps_transactions_without_timestamp() {
+            let mut account = account_with_window(DisputeWindow::Days(1));
+            account
+                .process_transaction(make_deposit(1, 1, Decimal::from(10)))
+                .unwrap();
+            account
+                .process_transaction(at(make_deposit(1, 2, Decimal::from(10)), 100 * DAY))
+                .unwrap();
+            account.process_transaction(make_dispute(1, 1)).unwrap();
+            assert_eq!(account.balance(None).held, Decimal::from(10));
+        }
+
+        #[test]
+        fn mixed_window_expires_transactions_without_timestamp() {
+            let mut account = account_with_window(DisputeWindow::DaysOrTransactions(30, 1));
+            account
+                .process_transaction(make_deposit(1, 1, Decimal::from(10)))
+                .unwrap();
+            account
+                .process_transaction(at(make_deposit(1, 2, Decimal::from(10)), 0))
+                .unwrap();
+            assert!(matches!(
+                account.process_transaction(make_dispute(1, 1)),
+                Err(AccountError::RuleViolation(
+                    RuleError::DisputeWindowExpired(1)
+                ))
+            ));
+            account
+                .process_transaction(at(make_deposit(1, 3, Decimal::from(10)), DAY))
+                .unwrap();
+            // the untimestamped deposit is evicted, the timestamped one is still disputable
+            assert_eq!(account.transactions.get(1).unwrap(), None);
+            account
+                .process_transaction(at(make_dispute(1, 2), 2 * DAY))
+                .unwrap();
+        }
+
+        #[test]
+        fn no_window_keeps_every_transaction() {
+            let mut account = Account::new(1);
+            for tx in 1..=10 {
+                account
+                    .process_transaction(make_deposit(1, tx, Decimal::from(10)))
+                    .unwrap();
+            }
+            assert_eq!(account.transactions.len(), 10);
+            assert!(account.expiry.is_empty());
+        }
+    }
+
+    mod currency_tests {
+        use super::*;
+
+        fn in_currency(mut tx: Transaction, currency: &str) -> Transaction {
+            tx.currency = Some(currency.to_string());
+            tx
+        }
+
+        #[test]
+        fn deposits_accumulate_per_currency() {
+            let mut account = Account::new(1);
+            account
+                .process_transaction(in_currency(make_deposit(1, 1, Decimal::from(10)), "USD"))
+                .unwrap();
+            account
+                .process_transaction(in_currency(make_deposit(1, 2, Decimal::from(5)), "EUR"))
+                .unwrap();
+            account
+                .process_transaction(make_deposit(1, 3, Decimal::from(1)))
+                .unwrap();
+            assert_eq!(account.balance(Some("USD")).available, Decimal::from(10));
+            assert_eq!(account.balance(Some("EUR")).available, Decimal::from(5));
+            assert_eq!(account.balance(None).available, Decimal::from(1));
+            assert!(account.has_currencies());
+        }
+
+        #[test]
+        fn withdrawal_checks_funds_in_its_currency() {
+            let mut account = Account::new(1);
+            account
+                .process_transaction(in_currency(make_deposit(1, 1, Decimal::from(10)), "USD"))
+                .unwrap();
+            let result = account
+                .process_transaction(in_currency(make_withdrawal(1, 2, Decimal::from(5)), "EUR"));
+            assert!(matches!(
+                result,
+                Err(AccountError::RuleViolation(RuleError::InsuficientFunds))
+            ));
+            assert_eq!(account.balance(Some("EUR")), Balance::default());
+            account
+                .process_transaction(in_currency(make_withdrawal(1, 3, Decimal::from(5)), "USD"))
+                .unwrap();
+            assert_eq!(account.balance(Some("USD")).available, Decimal::from(5));
+        }
+
+        #[test]
+        fn dispute_holds_funds_in_deposit_currency() {
+            let mut account = Account::new(1);
+            account
+                .process_transaction(in_currency(make_deposit(1, 1, Decimal::from(10)), "USD"))
+                .unwrap();
+            account
+                .process_transaction(in_currency(make_deposit(1, 2, Decimal::from(7)), "EUR"))
+                .unwrap();
+            // the currency of the dispute row itself is irrelevant
+            account
+                .process_transaction(in_currency(make_dispute(1, 1), "EUR"))
+                .unwrap();
+            assert_eq!(
+                account.balance(Some("USD")),
+                Balance {
+                    available: Decimal::ZERO,
+                    held: Decimal::from(10),
+                }
+            );
+            assert_eq!(account.balance(Some("EUR")).available, Decimal::from(7));
+            account.process_transaction(make_chargeback(1, 1)).unwrap();
+            assert_eq!(account.balance(Some("USD")).total(), Decimal::ZERO);
+            assert_eq!(account.balance(Some("EUR")).total(), Decimal::from(7));
+        }
+
+        #[test]
+        fn untouched_account_reports_zero_untagged_balance() {
+            let account = Account::new(1);
+            assert_eq!(
+                account.currency_balances(),
+                vec![(None, Balance::default())]
+            );
+            assert!(!account.has_currencies());
+        }
+    }
+
+    mod metadata_tests {
+        use super::*;
+
+        #[test]
+        fn stored_transaction_keeps_metadata() {
+            let mut account = Account::new(1);
+            let mut tx = make_deposit(1, 1, Decimal::from(10));
+            tx.timestamp = Some(1_700_000_000);
+            tx.currency = Some("EUR".to_string());
+            tx.reference = Some("INV-7".to_string());
+            tx.memo = Some("first payment".to_string());
+            account.process_transaction(tx).unwrap();
+            let record = account.find_transaction(&1).unwrap().unwrap();
+            assert_eq!(record.timestamp, Some(1_700_000_000));
+            assert_eq!(record.currency.as_deref(), Some("EUR"));
+            assert_eq!(record.reference.as_deref(), Some("INV-7"));
+            assert_eq!(record.memo.as_deref(), Some("first payment"));
+        }
+    }
+
+    mod overflow_tests {
+        use super::*;
+        use crate::{limits::ClientLimits, policy::DisputePolicy};
+        use proptest::prelude::*;
+
+        /// Everything a rejected transaction must leave untouched
+        type Observable = (
+            BTreeMap<Option<String>, Balance>,
+            bool,
+            u64,
+            Vec<(u32, TransactionRecord)>,
+        );
+
+        fn observable(account: &Account) -> Observable {
+            (
+                account.balances.clone(),
+                account.frozen,
+                account.sequence,
+                account.transactions.records().unwrap(),
+            )
+        }
+
+        #[test]
+        fn deposit_overflow_returns_error_and_does_not_modify_account() {
+            let mut account = Account::new(1);
+            account
+                .process_transaction(make_deposit(1, 1, Decimal::MAX))
+                .unwrap();
+            let before = observable(&account);
+            let result = account.process_transaction(make_deposit(1, 2, Decimal::ONE));
+            assert!(matches!(
+                result,
+                Err(AccountError::RuleViolation(RuleError::ArithmeticOverflow(
+                    2
+                )))
+            ));
+            assert_eq!(observable(&account), before);
+            assert!(account.find_transaction(&2).unwrap().is_none());
+        }
+
+        #[test]
+        fn total_overflow_returns_error() {
+            let mut account = Account::new(1);
+            account
+                .process_transaction(make_deposit(1, 1, Decimal::MAX))
+                .unwrap();
+            account.process_transaction(make_dispute(1, 1)).unwrap();
+            // available and held would each fit, but not their total
+            let result = account.process_transaction(make_deposit(1, 2, Decimal::ONE));
+            assert!(matches!(
+                result,
+                Err(AccountError::RuleViolation(RuleError::ArithmeticOverflow(
+                    2
+                )))
+            ));
+        }
+
+        #[test]
+        fn withdrawal_chargeback_overflow_keeps_dispute_open() {
+            let mut account = Account::with_policy(
+                1,
+                Policy {
+                    disputes: DisputePolicy::DepositsAndWithdrawals,
+                    ..Default::default()
+                },
+            );
+            account.balance_mut(None).available = Decimal::from(10);
+            account
+                .process_transaction(make_withdrawal(1, 1, Decimal::from(10)))
+                .unwrap();
+            account.process_transaction(make_dispute(1, 1)).unwrap();
+            account.balance_mut(None).available = Decimal::MAX - Decimal::from(5);
+            let before = observable(&account);
+            let result = account.process_transaction(make_chargeback(1, 1));
+            assert!(matches!(
+                result,
+                Err(AccountError::RuleViolation(RuleError::ArithmeticOverflow(
+                    1
+                )))
+            ));
+            assert_eq!(observable(&account), before);
+            assert!(account.has_dispute(&1).unwrap());
+        }
+
+        fn arb_amount() -> impl Strategy<Value = Option<Decimal>> {
+            prop_oneof![
+                Just(Some(Decimal::MAX)),
+                Just(Some(Decimal::MAX - Decimal::ONE)),
+                Just(Some(Decimal::MAX / Decimal::TWO)),
+                Just(Some(Decimal::ONE)),
+                Just(Some(Decimal::new(1, 28))),
+                Just(Some(Decimal::MIN)),
+                Just(None),
+                any::<(i64, u32)>()
+                    .prop_map(|(mantissa, scale)| Some(Decimal::new(mantissa, scale % 29))),
+            ]
+        }
+
+        fn arb_tx() -> impl Strategy<Value = Transaction> {
+            let r#type = prop_oneof![
+                Just(TransactionType::Deposit),
+                Just(TransactionType::Withdrawal),
+                Just(TransactionType::Dispute),
+                Just(TransactionType::Resolve),
+                Just(TransactionType::Chargeback),
+            ];
+            (r#type, 0u32..8, arb_amount())
+                .prop_map(|(r#type, tx, amount)| Transaction::new(r#type, 1, tx, amount))
+        }
+
+        proptest! {
+            /// Extreme amounts never panic, every balance and its total stay representable,
+            /// and a rejected transaction leaves the account as it was.
+            #[test]
+            fn extreme_amounts_never_panic_or_corrupt_the_account(
+                withdrawal_disputes in any::<bool>(),
+                credit in prop_oneof![Just(Decimal::ZERO), Just(Decimal::MAX)],
+                stream in prop::collection::vec(arb_tx(), 0..64),
+            ) {
+                let mut account = Account::with_policy(
+                    1,
+                    Policy {
+                        disputes: if withdrawal_disputes {
+                            DisputePolicy::DepositsAndWithdrawals
+                        } else {
+                            DisputePolicy::DepositsOnly
+                        },
+                        ..Default::default()
+                    },
+                );
+                account.limits = ClientLimits {
+                    credit,
+                    ..Default::default()
+                };
+                for tx in stream {
+                    let before = observable(&account);
+                    if account.process_transaction(tx).is_err() {
+                        prop_assert_eq!(observable(&account), before);
+                    }
+                    for (_, balance) in account.currency_balances() {
+                        prop_assert!(balance.available.checked_add(balance.held).is_some());
+                    }
+                }
+            }
+        }
+    }
+
+    mod history_tests {
+        use super::*;
+
+        fn recording_account() -> Account {
+            let mut account = Account::new(1);
+            account.record_history();
+            account
+        }
+
+        fn entry(
+            account: &Account,
+            index: usize,
+        ) -> (u64, Option<u32>, HistoryType, Decimal, Decimal) {
+            let entry = &account.history()[index];
+            (
+                entry.sequence,
+                entry.tx,
+                entry.r#type,
+                entry.available,
+                entry.held,
+            )
+        }
+
+        #[test]
+        fn history_records_balance_after_each_transaction() {
+            let mut account = recording_account();
+            for tx in [
+                make_deposit(1, 1, Decimal::from(100)),
+                make_withdrawal(1, 2, Decimal::from(30)),
+                make_dispute(1, 1),
+                make_resolve(1, 1),
+            ] {
+                account.process_transaction(tx).unwrap();
+            }
+            let entries: Vec<_> = (0..4).map(|index| entry(&account, index)).collect();
+            assert_eq!(
+                entries,
+                vec![
+                    (
+                        1,
+                        Some(1),
+                        HistoryType::Transaction(TransactionType::Deposit),
+                        Decimal::from(100),
+                        Decimal::ZERO
+                    ),
+                    (
+                        2,
+                        Some(2),
+                        HistoryType::Transaction(TransactionType::Withdrawal),
+                        Decimal::from(70),
+                        Decimal::ZERO
+                    ),
+                    (
+                        3,
+                        Some(1),
+                        HistoryType::Transaction(TransactionType::Dispute),
+                        Decimal::from(-30),
+                        Decimal::from(100)
+                    ),
+                    (
+                        4,
+                        Some(1),
+                        HistoryType::Transaction(TransactionType::Resolve),
+                        Decimal::from(70),
+                        Decimal::ZERO
+                    ),
+                ]
+            );
+            assert_eq!(account.history()[2].amount, Decimal::from(100));
+        }
+
+        #[test]
+        fn rejected_transactions_are_not_recorded() {
+            let mut account = recording_account();
+            account
+                .process_transaction(make_deposit(1, 1, Decimal::from(10)))
+                .unwrap();
+            assert!(
+                account
+                    .process_transaction(make_withdrawal(1, 2, Decimal::from(20)))
+                    .is_err()
+            );
+            assert_eq!(account.history().len(), 1);
+        }
+
+        #[test]
+        fn disputes_are_recorded_in_the_currency_of_their_transaction() {
+            let mut account = recording_account();
+            let mut deposit = make_deposit(1, 1, Decimal::from(5));
+            deposit.currency = Some("EUR".to_string());
+            account.process_transaction(deposit).unwrap();
+            account
+                .process_transaction(make_deposit(1, 2, Decimal::ONE))
+                .unwrap();
+            account.process_transaction(make_dispute(1, 1)).unwrap();
+            let dispute = &account.history()[2];
+            assert_eq!(dispute.currency.as_deref(), Some("EUR"));
+            assert_eq!(dispute.available, Decimal::ZERO);
+            assert_eq!(dispute.held, Decimal::from(5));
+        }
+
+        #[test]
+        fn history_is_only_recorded_when_asked_for() {
+            let mut account = Account::new(1);
+            account
+                .process_transaction(make_deposit(1, 1, Decimal::ONE))
+                .unwrap();
+            assert!(account.history().is_empty());
+            account.record_history();
+            account
+                .process_transaction(make_deposit(1, 2, Decimal::ONE))
+                .unwrap();
+            assert_eq!(entry(&account, 1).1, Some(2));
+        }
+
+        #[test]
+        fn history_opens_with_the_balances_already_reached() {
+            let mut account = Account::new(1);
+            let mut deposit = make_deposit(1, 1, Decimal::from(5));
+            deposit.currency = Some("EUR".to_string());
+            account.process_transaction(deposit).unwrap();
+            account
+                .process_transaction(make_deposit(1, 2, Decimal::from(3)))
+                .unwrap();
+            account.process_transaction(make_dispute(1, 2)).unwrap();
+            account.record_history();
+            let opening: Vec<_> = account
+                .history()
+                .iter()
+                .map(|entry| {
+                    (
+                        entry.sequence,
+                        entry.tx,
+                        entry.r#type,
+                        entry.currency.as_deref(),
+                        entry.amount,
+                        entry.held,
+                    )
+                })
+                .collect();
+            assert_eq!(
+                opening,
+                vec![
+                    (
+                        3,
+                        None,
+                        HistoryType::Opening,
+                        None,
+                        Decimal::from(3),
+                        Decimal::from(3)
+                    ),
+                    (
+                        3,
+                        None,
+                        HistoryType::Opening,
+                        Some("EUR"),
+                        Decimal::from(5),
+                        Decimal::ZERO
+                    ),
+                ]
+            );
+        }
+
+        #[test]
+        fn new_account_history_has_no_opening() {
+            let mut account = recording_account();
+            account
+                .process_transaction(make_deposit(1, 1, Decimal::ONE))
+                .unwrap();
+            assert_eq!(account.history().len(), 1);
+        }
+    }
+
+    mod atomicity_tests {
+        use super::*;
+        use crate::{
+            limits::VelocityLimits,
+            policy::{DisputePolicy, DisputeWindow},
+            state::AccountState,
+        };
+        use proptest::prelude::*;
+
+        const NOW: u64 = 1_000_000;
+        const DAY: u64 = 86_400;
+
+        /// Every piece of state of the account, balances and stored transactions down to the
+        /// dispute and velocity windows, as it would be saved
+        fn snapshot(account: &Account) -> Vec<u8> {
+            serde_json::to_vec(&AccountState::try_from(account).unwrap()).unwrap()
+        }
+
+        fn in_eur(mut tx: Transaction) -> Transaction {
+            tx.currency = Some("EUR".to_string());
+            tx
+        }
+
+        /// Account with a bit of every kind of state: two currencies, a withdrawal in the
+        /// velocity window, an open dispute and transactions tracked by the dispute window
+        fn busy_account() -> Account {
+            let mut account = Account::with_policy(
+                1,
+                Policy {
+                    disputes: DisputePolicy::DepositsAndWithdrawals,
+                    dispute_window: Some(DisputeWindow::Days(30)),
+                },
+            );
+            account.limits.velocity = VelocityLimits {
+                max_withdrawal: Some(Decimal::from(100)),
+                daily_amount: Some(Decimal::from(150)),
+                daily_count: Some(2),
+                window_transactions: None,
+            };
+            for tx in [
+                at(make_deposit(1, 1, Decimal::from(500)), NOW),
+                at(in_eur(make_deposit(1, 2, Decimal::from(200))), NOW),
+                at(make_withdrawal(1, 3, Decimal::from(60)), NOW + 10),
+                at(make_dispute(1, 2), NOW + 20),
+            ] {
+                account.process_transaction(tx).unwrap();
+            }
+            account
+        }
+
+        /// Account and transaction it rejects with the given violation.
+        ///
+        /// The match is exhaustive, so a new violation does not compile until it gets a
+        /// scenario here. It is only exercised once it is also listed in [`violations`].
+        fn scenario(violation: &RuleError) -> Option<(Account, Transaction)> {
+            let mut account = busy_account();
+            let later = NOW + 100;
+            let tx = match violation {
+                RuleError::AccountFrozen => {
+                    account
+                        .process_transaction(at(make_chargeback(1, 2), NOW + 30))
+                        .unwrap();
+                    make_deposit(1, 4, Decimal::ONE)
+                }
+                RuleError::InsuficientFunds => in_eur(make_withdrawal(1, 4, Decimal::from(50))),
+                RuleError::DepositNotFound(_) => make_dispute(1, 4),
+                RuleError::TrasactionNotOnDispute(_) => make_resolve(1, 1),
+                RuleError::TransactionAlreadyDisputed(_) => make_dispute(1, 2),
+                RuleError::MissingAmount(_) => {
+                    Transaction::new(TransactionType::Deposit, 1, 4, None)
+                }
+                // checked by the ledger before the account sees the transaction
+                RuleError::DuplicateTransaction(_) => return None,
+                RuleError::DisputeWindowExpired(_) => {
+                    return Some((account, at(make_dispute(1, 1), NOW + 31 * DAY)));
+                }
+                RuleError::WithdrawalAboveMaximum(_) => make_withdrawal(1, 4, Decimal::from(101)),
+                RuleError::DailyLimitExceeded(_) => make_withdrawal(1, 4, Decimal::from(95)),
+                RuleError::TooManyWithdrawals(_) => {
+                    account
+                        .process_transaction(at(make_withdrawal(1, 4, Decimal::TEN), NOW + 30))
+                        .unwrap();
+                    make_withdrawal(1, 5, Decimal::TEN)
+                }
+                RuleError::NonPositiveAmount(_) => make_deposit(1, 4, Decimal::ZERO),
+                RuleError::ArithmeticOverflow(_) => make_deposit(1, 4, Decimal::MAX),
+            };
+            Some((account, at(tx, later)))
+        }
+
+        /// One of each violation, with the transaction its scenario rejects
+        fn violations() -> Vec<RuleError> {
+            vec![
+                RuleError::AccountFrozen,
+                RuleError::InsuficientFunds,
+                RuleError::DepositNotFound(4),
+                RuleError::TrasactionNotOnDispute(1),
+                RuleError::TransactionAlreadyDisputed(2),
+                RuleError::MissingAmount(4),
+                RuleError::DuplicateTransaction(4),
+                RuleError::DisputeWindowExpired(1),
+                RuleError::WithdrawalAboveMaximum(4),
+                RuleError::DailyLimitExceeded(4),
+                RuleError::TooManyWithdrawals(5),
+                RuleError::NonPositiveAmount(4),
+                RuleError::ArithmeticOverflow(4),
+            ]
+        }
+
+        #[test]
+        fn every_violation_leaves_the_account_untouched() {
+            for violation in violations() {
+                let Some((mut account, tx)) = scenario(&violation) else {
+                    continue;
+                };
+                let before = snapshot(&account);
+                let err = account.process_transaction(tx).unwrap_err();
+                assert_eq!(err.to_string(), violation.to_string());
+                assert_eq!(snapshot(&account), before, "{}", violation.code());
+            }
+        }
+
+        #[test]
+        fn mismatching_account_leaves_the_account_untouched() {
+            let mut account = busy_account();
+            let before = snapshot(&account);
+            let result = account.process_transaction(make_deposit(2, 4, Decimal::ONE));
+            assert!(matches!(
+                result,
+                Err(AccountError::MismatchingAccounts(1, 2))
+            ));
+            assert_eq!(snapshot(&account), before);
+        }
+
+        /// Faults injected into a [`FlakyStore`]
+        #[derive(Debug, Default)]
+        struct Faults {
+            fail_inserts: bool,
+            /// Removals that still succeed, or `None` for no limit
+            removals: Option<usize>,
+        }
+
+        /// Memory store that fails on demand
+        #[derive(Debug, Default)]
+        struct FlakyStore {
+            store: crate::store::MemoryStore,
+            faults: std::sync::Arc<std::sync::Mutex<Faults>>,
+        }
+
+        impl FlakyStore {
+            fn failure() -> StoreError {
+                StoreError::Io(std::io::Error::other("flaky store"))
+            }
+        }
+
+        impl TransactionStore for FlakyStore {
+            fn get(&self, tx_id: u32) -> Result<Option<TransactionRecord>, StoreError> {
+                self.store.get(tx_id)
+            }
+
+            fn insert(&mut self, tx_id: u32, record: TransactionRecord) -> Result<(), StoreError> {
+                if self.faults.lock().unwrap().fail_inserts {
+                    return Err(Self::failure());
+                }
+                self.store.insert(tx_id, record)
+            }
+
+            fn remove(&mut self, tx_id: u32) -> Result<(), StoreError> {
+                match &mut self.faults.lock().unwrap().removals {
+                    Some(0) => return Err(Self::failure()),
+                    Some(left) => *left -= 1,
+                    None => {}
+                }
+                self.store.remove(tx_id)
+            }
+
+            fn records(&self) -> Result<Vec<(u32, TransactionRecord)>, StoreError> {
+                self.store.records()
+            }
+
+            fn len(&self) -> usize {
+                self.store.len()
+            }
+        }
+
+        /// Account with a one day window over a flaky store holding two expired deposits
+        fn flaky_account() -> (Account, std::sync::Arc<std::sync::Mutex<Faults>>) {
+            let policy = Policy {
+                dispute_window: Some(DisputeWindow::Days(1)),
+                ..Default::default()
+            };
+            let store = FlakyStore::default();
+            let faults = store.faults.clone();
+            let mut account = Account::with_store(1, policy, Box::new(store));
+            for tx in [1, 2] {
+                account
+                    .process_transaction(at(make_deposit(1, tx, Decimal::TEN), NOW))
+                    .unwrap();
+            }
+            (account, faults)
+        }
+
+        fn is_queued(account: &Account, tx_id: u32) -> bool {
+            account.expiry.iter().any(|(_, queued)| *queued == tx_id)
+        }
+
+        #[test]
+        fn partial_eviction_keeps_the_rest_queued() {
+            let (mut account, faults) = flaky_account();
+            faults.lock().unwrap().removals = Some(1);
+            account
+                .process_transaction(at(make_deposit(1, 3, Decimal::ONE), NOW + 2 * DAY))
+                .unwrap();
+            assert_eq!(account.balance(None).available, Decimal::from(21));
+            assert_eq!(account.transactions.get(1).unwrap(), None);
+            assert!(!is_queued(&account, 1));
+            assert!(account.transactions.get(2).unwrap().is_some());
+            assert!(is_queued(&account, 2));
+
+            faults.lock().unwrap().removals = None;
+            account
+                .process_transaction(at(make_deposit(1, 4, Decimal::ONE), NOW + 2 * DAY))
+                .unwrap();
+            assert_eq!(account.transactions.get(2).unwrap(), None);
+            assert!(!is_queued(&account, 2));
+        }
+
+        #[test]
+        fn failing_insert_evicts_nothing() {
+            let (mut account, faults) = flaky_account();
+            faults.lock().unwrap().fail_inserts = true;
+            let before = snapshot(&account);
+            let result =
+                account.process_transaction(at(make_deposit(1, 3, Decimal::ONE), NOW + 2 * DAY));
+            assert!(matches!(result, Err(AccountError::Store(_))));
+            assert_eq!(snapshot(&account), before);
+            assert_eq!(account.transactions.len(), 2);
+        }
+
+        #[test]
+        fn scenarios_are_accepted_without_the_violation() {
+            // the busy account takes a valid transaction of each type, so the scenarios
+            // above fail on their own violation only
+            let mut account = busy_account();
+            for tx in [
+                make_deposit(1, 4, Decimal::ONE),
+                make_withdrawal(1, 5, Decimal::TEN),
+                make_dispute(1, 1),
+                make_resolve(1, 1),
+                make_chargeback(1, 2),
+            ] {
+                account.process_transaction(at(tx, NOW + 100)).unwrap();
+            }
+            assert!(account.frozen);
+        }
+
+        fn arb_tx() -> impl Strategy<Value = Transaction> {
+            let r#type = prop_oneof![
+                Just(TransactionType::Deposit),
+                Just(TransactionType::Withdrawal),
+                Just(TransactionType::Dispute),
+                Just(TransactionType::Resolve),
+                Just(TransactionType::Chargeback),
+            ];
+            let amount = prop_oneof![
+                Just(None),
+                Just(Some(Decimal::MAX)),
+                (-50i64..200).prop_map(|amount| Some(Decimal::from(amount))),
+            ];
+            (r#type, 1u32..8, amount, 0..4 * DAY, any::<bool>()).prop_map(
+                |(r#type, tx, amount, offset, eur)| {
+                    let tx = at(Transaction::new(r#type, 1, tx, amount), NOW + offset);
+                    if eur { in_eur(tx) } else { tx }
+                },
+            )
+        }
+
+        proptest! {
+            /// Whatever the stream, a rejected transaction leaves the account as it was.
+            #[test]
+            fn rejected_transactions_leave_the_account_untouched(
+                stream in prop::collection::vec(arb_tx(), 0..64),
+            ) {
+                let mut account = busy_account();
+                for tx in stream {
+                    let before = snapshot(&account);
+                    if account.process_transaction(tx).is_err() {
+                        prop_assert_eq!(snapshot(&account), before);
+                    }
+                }
+            }
+        }
+    }
+
+    mod account_error_tests {
+        use super::*;
+
+        #[test]
+        fn mismatching_accounts_returns_error() {
+            let mut account = Account::new(1);
+            let result = account.process_transaction(make_deposit(2, 1, Decimal::from(100)));
+            assert!(matches!(
+                result,
+                Err(AccountError::MismatchingAccounts(1, 2))
+            ));
+        }
+
+        #[test]
+        fn codes_are_stable() {
+            assert_eq!(
+                AccountError::MismatchingAccounts(1, 2).code(),
+                "mismatching_accounts"
+            );
+            assert_eq!(
+                AccountError::RuleViolation(RuleError::AccountFrozen).code(),
+                "account_frozen"
+            );
+            assert_eq!(
+                AccountError::Store(StoreError::Corrupt(1)).code(),
+                "store_failure"
+            );
         }
     }
 }
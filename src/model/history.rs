@@ -0,0 +1,41 @@
+use rust_decimal::Decimal;
+
+use super::TransactionType;
+
+/// What an entry of the history records
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HistoryType {
+    /// Balance the account already had when its history started to be recorded
+    Opening,
+    #[serde(untagged)]
+    Transaction(TransactionType),
+}
+
+/// A transaction applied to an account, with the balance of its currency right after it.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct HistoryEntry {
+    /// Account sequence the transaction was applied at
+    pub sequence: u64,
+    /// Transaction applied, none for an opening balance
+    pub tx: Option<u32>,
+    pub r#type: HistoryType,
+
+    /// Amount moved: the transaction amount, the disputed one for a dispute, resolve or
+    /// chargeback, or the total carried over for an opening balance
+    pub amount: Decimal,
+
+    /// Unix time in seconds at which the transaction happened, if known
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<u64>,
+
+    /// Currency of the balance the transaction moved
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub currency: Option<String>,
+
+    /// Available funds in the currency after the transaction
+    pub available: Decimal,
+
+    /// Held funds in the currency after the transaction
+    pub held: Decimal,
+}
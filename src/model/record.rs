@@ -0,0 +1,128 @@
+use rust_decimal::Decimal;
+
+use super::Transaction;
+
+/// Lifecycle of a stored transaction.
+///
+/// ```text
+/// Processed ──dispute──▶ Disputed ──resolve────▶ Resolved
+///                            └──────chargeback──▶ ChargedBack
+/// ```
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransactionState {
+    #[default]
+    Processed,
+    Disputed,
+    Resolved,
+    ChargedBack,
+}
+
+impl TransactionState {
+    /// Whether the lifecycle allows moving from `self` to `next`
+    pub fn can_transition_to(self, next: TransactionState) -> bool {
+        matches!(
+            (self, next),
+            (Self::Processed, Self::Disputed)
+                | (Self::Disputed, Self::Resolved)
+                | (Self::Disputed, Self::ChargedBack)
+        )
+    }
+}
+
+/// Kind of a stored transaction, which decides how a dispute moves its funds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransactionKind {
+    Deposit,
+    Withdrawal,
+}
+
+/// A stored transaction that may later be referenced by a dispute.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct TransactionRecord {
+    pub kind: TransactionKind,
+    pub amount: Decimal,
+    pub state: TransactionState,
+
+    /// Account sequence the transaction was accepted at
+    #[serde(default)]
+    pub sequence: u64,
+
+    /// Unix time in seconds at which the transaction happened, if known
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<u64>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub currency: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reference: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memo: Option<String>,
+}
+
+impl TransactionRecord {
+    pub fn new(kind: TransactionKind, amount: Decimal) -> Self {
+        Self {
+            kind,
+            amount,
+            state: TransactionState::Processed,
+            sequence: 0,
+            timestamp: None,
+            currency: None,
+            reference: None,
+            memo: None,
+        }
+    }
+
+    /// Record of an accepted deposit or withdrawal, carrying over its metadata
+    pub fn from_transaction(kind: TransactionKind, amount: Decimal, tx: &Transaction) -> Self {
+        Self {
+            timestamp: tx.timestamp,
+            currency: tx.currency.clone(),
+            reference: tx.reference.clone(),
+            memo: tx.memo.clone(),
+            ..Self::new(kind, amount)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use TransactionState::*;
+
+    #[test]
+    fn legal_transitions_are_allowed() {
+        assert!(Processed.can_transition_to(Disputed));
+        assert!(Disputed.can_transition_to(Resolved));
+        assert!(Disputed.can_transition_to(ChargedBack));
+    }
+
+    #[test]
+    fn illegal_transitions_are_rejected() {
+        let states = [Processed, Disputed, Resolved, ChargedBack];
+        let legal = [
+            (Processed, Disputed),
+            (Disputed, Resolved),
+            (Disputed, ChargedBack),
+        ];
+        for from in states {
+            for to in states {
+                if !legal.contains(&(from, to)) {
+                    assert!(!from.can_transition_to(to), "{from:?} -> {to:?}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn new_record_is_processed() {
+        assert_eq!(
+            TransactionRecord::new(TransactionKind::Deposit, Decimal::ONE).state,
+            Processed
+        );
+    }
+}
@@ -1,6 +1,6 @@
-use rust_decimal::{Decimal, RoundingStrategy};
+use rust_decimal::Decimal;
 
-#[derive(Debug, PartialEq, serde::Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, serde::Deserialize, serde::Serialize)]
 #[serde(rename_all = "lowercase")]
 pub enum TransactionType {
     Deposit,
@@ -10,19 +10,40 @@ pub enum TransactionType {
     Chargeback,
 }
 
-fn deserialize_amount_4_dp<'de, D>(deserializer: D) -> Result<Option<Decimal>, D::Error>
-where
-    D: serde::Deserializer<'de>,
-{
-    let value = <Option<Decimal> as serde::Deserialize>::deserialize(deserializer)?;
-    Ok(value.map(|v| v.round_dp_with_strategy(4, RoundingStrategy::ToZero)))
-}
-
-#[derive(Debug, serde::Deserialize)]
+#[derive(Debug, Clone, PartialEq, serde::Deserialize, serde::Serialize)]
 pub struct Transaction {
     pub r#type: TransactionType,
     pub client: u16,
     pub tx: u32,
-    #[serde(default, deserialize_with = "deserialize_amount_4_dp")]
+    /// Amount as given; [`crate::input::TransactionReader`] brings it to the ledger precision
+    #[serde(default)]
     pub amount: Option<Decimal>,
+    /// Unix time in seconds at which the transaction happened, if known
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<u64>,
+    /// Currency code of the amount
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub currency: Option<String>,
+    /// External reference, such as an invoice or payment ID
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reference: Option<String>,
+    /// Free-form note
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memo: Option<String>,
+}
+
+impl Transaction {
+    /// Transaction without timestamp, currency, reference or memo
+    pub fn new(r#type: TransactionType, client: u16, tx: u32, amount: Option<Decimal>) -> Self {
+        Self {
+            r#type,
+            client,
+            tx,
+            amount,
+            timestamp: None,
+            currency: None,
+            reference: None,
+            memo: None,
+        }
+    }
 }
@@ -0,0 +1,492 @@
+use std::{collections::BTreeMap, io::Write, path::Path};
+
+use serde::Serialize;
+
+use crate::model::{Account, AccountError, HistoryType, Transaction, TransactionType};
+
+#[derive(Debug, thiserror::Error)]
+pub enum OutputError {
+    #[error(transparent)]
+    Csv(#[from] csv::Error),
+
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+/// Formats records can be written in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Format {
+    Csv,
+    Json,
+    Jsonl,
+}
+
+impl Format {
+    /// Infer the format from a file extension, if it is a known one
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()? {
+            "csv" => Some(Self::Csv),
+            "json" => Some(Self::Json),
+            "jsonl" | "ndjson" => Some(Self::Jsonl),
+            _ => None,
+        }
+    }
+}
+
+/// Writes serializable records one at a time in a given [`Format`].
+///
+/// [`RecordWriter::finish`] must be called once all records are written, so that
+/// formats with a trailer (such as the closing bracket of a JSON array) are complete.
+pub enum RecordWriter<W: Write> {
+    Csv(Box<csv::Writer<W>>),
+    Json { writer: W, empty: bool },
+    Jsonl(W),
+}
+
+impl<W: Write> RecordWriter<W> {
+    pub fn new(format: Format, writer: W) -> Self {
+        match format {
+            Format::Csv => Self::Csv(Box::new(csv::Writer::from_writer(writer))),
+            Format::Json => Self::Json {
+                writer,
+                empty: true,
+            },
+            Format::Jsonl => Self::Jsonl(writer),
+        }
+    }
+
+    pub fn write<T: Serialize>(&mut self, record: &T) -> Result<(), OutputError> {
+        match self {
+            Self::Csv(writer) => writer.serialize(record)?,
+            Self::Json { writer, empty } => {
+                writer.write_all(if *empty { b"[" } else { b"," })?;
+                serde_json::to_writer(&mut *writer, record)?;
+                *empty = false;
+            }
+            Self::Jsonl(writer) => {
+                serde_json::to_writer(&mut *writer, record)?;
+                writer.write_all(b"\n")?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Terminate the output and flush buffered records to the underlying writer
+    pub fn finish(self) -> Result<(), OutputError> {
+        match self {
+            Self::Csv(mut writer) => writer.flush()?,
+            Self::Json { mut writer, empty } => {
+                writer.write_all(if empty { b"[]\n" } else { b"]\n" })?;
+                writer.flush()?;
+            }
+            Self::Jsonl(mut writer) => writer.flush()?,
+        }
+        Ok(())
+    }
+}
+
+/// Amounts of a balance normalized for display
+#[derive(Debug, PartialEq, Serialize)]
+pub struct BalanceRecord {
+    pub available: String,
+    pub held: String,
+    pub total: String,
+
+    /// Remaining credit, only reported when credit limits are in use
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub credit: Option<String>,
+}
+
+impl BalanceRecord {
+    /// Balance of the account in `currency`, with its remaining credit if `credit` is set
+    pub fn new(account: &Account, currency: Option<&str>, credit: bool) -> Self {
+        let balance = account.balance(currency);
+        Self {
+            available: balance.available.normalize().to_string(),
+            held: balance.held.normalize().to_string(),
+            total: balance.total().normalize().to_string(),
+            credit: credit.then(|| account.remaining_credit(currency).normalize().to_string()),
+        }
+    }
+}
+
+/// Account balance as written to the account output when transactions carry no currency,
+/// the same layout as the [`Account`] itself plus the remaining credit when requested
+#[derive(Debug, PartialEq, Serialize)]
+pub struct AccountRecord {
+    pub client: u16,
+    pub available: String,
+    pub held: String,
+    pub total: String,
+    pub locked: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub credit: Option<String>,
+}
+
+impl AccountRecord {
+    pub fn new(account: &Account, credit: bool) -> Self {
+        let BalanceRecord {
+            available,
+            held,
+            total,
+            credit,
+        } = BalanceRecord::new(account, None, credit);
+        Self {
+            client: account.client,
+            available,
+            held,
+            total,
+            locked: account.frozen,
+            credit,
+        }
+    }
+}
+
+/// Balance of an account in one currency, as written to the CSV account output once
+/// transactions carry currencies. Transactions without currency have an empty one.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct CurrencyRecord {
+    pub client: u16,
+    pub currency: String,
+    pub available: String,
+    pub held: String,
+    pub total: String,
+    pub locked: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub credit: Option<String>,
+}
+
+impl CurrencyRecord {
+    /// One record per currency of the account, sorted by currency
+    pub fn of(account: &Account, credit: bool) -> impl Iterator<Item = Self> + '_ {
+        account
+            .currency_balances()
+            .into_iter()
+            .map(move |(currency, _)| {
+                let BalanceRecord {
+                    available,
+                    held,
+                    total,
+                    credit,
+                } = BalanceRecord::new(account, currency, credit);
+                Self {
+                    client: account.client,
+                    currency: currency.unwrap_or_default().to_string(),
+                    available,
+                    held,
+                    total,
+                    locked: account.frozen,
+                    credit,
+                }
+            })
+    }
+}
+
+/// Account with its balances keyed by currency, as written to the JSON account output once
+/// transactions carry currencies. Transactions without currency have an empty one.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct MultiCurrencyRecord {
+    pub client: u16,
+    pub balances: BTreeMap<String, BalanceRecord>,
+    pub locked: bool,
+}
+
+impl MultiCurrencyRecord {
+    pub fn new(account: &Account, credit: bool) -> Self {
+        Self {
+            client: account.client,
+            balances: account
+                .currency_balances()
+                .into_iter()
+                .map(|(currency, _)| {
+                    (
+                        currency.unwrap_or_default().to_string(),
+                        BalanceRecord::new(account, currency, credit),
+                    )
+                })
+                .collect(),
+            locked: account.frozen,
+        }
+    }
+}
+
+/// A transaction applied to an account, with the running balance of its currency, as
+/// written to a client statement
+#[derive(Debug, PartialEq, Serialize)]
+pub struct StatementRecord {
+    pub client: u16,
+    pub sequence: u64,
+    pub tx: Option<u32>,
+    pub r#type: HistoryType,
+    pub timestamp: Option<u64>,
+    pub currency: Option<String>,
+    pub amount: String,
+    pub available: String,
+    pub held: String,
+    pub total: String,
+}
+
+impl StatementRecord {
+    /// One record per transaction applied to the account, in order, after its opening
+    /// balances if it had any
+    pub fn of(account: &Account) -> impl Iterator<Item = Self> + '_ {
+        account.history().iter().map(|entry| Self {
+            client: account.client,
+            sequence: entry.sequence,
+            tx: entry.tx,
+            r#type: entry.r#type,
+            timestamp: entry.timestamp,
+            currency: entry.currency.clone(),
+            amount: entry.amount.normalize().to_string(),
+            available: entry.available.normalize().to_string(),
+            held: entry.held.normalize().to_string(),
+            total: (entry.available + entry.held).normalize().to_string(),
+        })
+    }
+}
+
+/// A transaction rejected by the ledger, as written to the error log
+#[derive(Debug, PartialEq, Serialize)]
+pub struct ErrorRecord {
+    /// Input the transaction was read from
+    pub input: String,
+    pub line: u64,
+    pub client: u16,
+    pub tx: u32,
+    pub r#type: TransactionType,
+    pub timestamp: Option<u64>,
+    pub currency: Option<String>,
+    pub reference: Option<String>,
+    pub memo: Option<String>,
+    pub error_code: &'static str,
+    pub message: String,
+}
+
+impl ErrorRecord {
+    pub fn new(input: &str, line: u64, tx: &Transaction, error: &AccountError) -> Self {
+        Self {
+            input: input.to_string(),
+            line,
+            client: tx.client,
+            tx: tx.tx,
+            r#type: tx.r#type,
+            timestamp: tx.timestamp,
+            currency: tx.currency.clone(),
+            reference: tx.reference.clone(),
+            memo: tx.memo.clone(),
+            error_code: error.code(),
+            message: error.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rules::RuleError;
+    use rust_decimal::Decimal;
+
+    fn make_record() -> ErrorRecord {
+        let tx = Transaction {
+            reference: Some("INV-1".to_string()),
+            ..Transaction::new(TransactionType::Withdrawal, 1, 7, None)
+        };
+        ErrorRecord::new(
+            "day1.csv",
+            3,
+            &tx,
+            &AccountError::RuleViolation(RuleError::InsuficientFunds),
+        )
+    }
+
+    fn write_all(format: Format, records: &[ErrorRecord]) -> String {
+        let mut buffer = Vec::new();
+        let mut writer = RecordWriter::new(format, &mut buffer);
+        for record in records {
+            writer.write(record).unwrap();
+        }
+        writer.finish().unwrap();
+        String::from_utf8(buffer).unwrap()
+    }
+
+    #[test]
+    fn format_is_inferred_from_extension() {
+        assert_eq!(Format::from_path(Path::new("e.csv")), Some(Format::Csv));
+        assert_eq!(Format::from_path(Path::new("e.json")), Some(Format::Json));
+        assert_eq!(Format::from_path(Path::new("e.jsonl")), Some(Format::Jsonl));
+        assert_eq!(Format::from_path(Path::new("e.txt")), None);
+        assert_eq!(Format::from_path(Path::new("e")), None);
+    }
+
+    #[test]
+    fn error_record_carries_code_and_message() {
+        let record = make_record();
+        assert_eq!(record.error_code, "insufficient_funds");
+        assert_eq!(record.message, "insufficient funds");
+    }
+
+    #[test]
+    fn csv_error_log_has_header_and_rows() {
+        assert_eq!(
+            write_all(Format::Csv, &[make_record()]),
+            "input,line,client,tx,type,timestamp,currency,reference,memo,error_code,message\n\
+             day1.csv,3,1,7,withdrawal,,,INV-1,,insufficient_funds,insufficient funds\n"
+        );
+    }
+
+    #[test]
+    fn json_writes_an_array() {
+        let object = "{\"input\":\"day1.csv\",\"line\":3,\"client\":1,\"tx\":7,\"type\":\"withdrawal\",\
+                      \"timestamp\":null,\"currency\":null,\"reference\":\"INV-1\",\"memo\":null,\
+                      \"error_code\":\"insufficient_funds\",\"message\":\"insufficient funds\"}";
+        assert_eq!(
+            write_all(Format::Json, &[make_record(), make_record()]),
+            format!("[{object},{object}]\n")
+        );
+    }
+
+    fn multi_currency_account() -> Account {
+        let mut account = Account::new(1);
+        account.balance_mut(None).available = Decimal::new(15, 1);
+        account.balance_mut(Some("USD")).available = Decimal::from(2);
+        account.balance_mut(Some("EUR")).held = Decimal::new(2500, 3);
+        account
+    }
+
+    #[test]
+    fn currency_records_have_one_row_per_currency() {
+        let mut buffer = Vec::new();
+        let mut writer = RecordWriter::new(Format::Csv, &mut buffer);
+        for record in CurrencyRecord::of(&multi_currency_account(), false) {
+            writer.write(&record).unwrap();
+        }
+        writer.finish().unwrap();
+        assert_eq!(
+            String::from_utf8(buffer).unwrap(),
+            "client,currency,available,held,total,locked\n\
+             1,,1.5,0,1.5,false\n\
+             1,EUR,0,2.5,2.5,false\n\
+             1,USD,2,0,2,false\n"
+        );
+    }
+
+    #[test]
+    fn multi_currency_record_maps_balances() {
+        let record = MultiCurrencyRecord::new(&multi_currency_account(), false);
+        assert_eq!(
+            serde_json::to_string(&record).unwrap(),
+            "{\"client\":1,\"balances\":{\
+             \"\":{\"available\":\"1.5\",\"held\":\"0\",\"total\":\"1.5\"},\
+             \"EUR\":{\"available\":\"0\",\"held\":\"2.5\",\"total\":\"2.5\"},\
+             \"USD\":{\"available\":\"2\",\"held\":\"0\",\"total\":\"2\"}},\
+             \"locked\":false}"
+        );
+    }
+
+    #[test]
+    fn account_record_reports_remaining_credit_on_request() {
+        let mut account = Account::new(2);
+        account.balance_mut(None).available = Decimal::from(-30);
+        account.limits.credit = Decimal::from(100);
+        let mut buffer = Vec::new();
+        let mut writer = RecordWriter::new(Format::Csv, &mut buffer);
+        writer.write(&AccountRecord::new(&account, true)).unwrap();
+        writer.finish().unwrap();
+        assert_eq!(
+            String::from_utf8(buffer).unwrap(),
+            "client,available,held,total,locked,credit\n2,-30,0,-30,false,70\n"
+        );
+    }
+
+    #[test]
+    fn account_record_serializes_normalized_decimals_as_strings() {
+        let mut account = Account::new(1);
+        account.balance_mut(None).available = Decimal::new(15000, 4);
+        account.balance_mut(None).held = Decimal::new(25, 1);
+        assert_eq!(
+            serde_json::to_string(&AccountRecord::new(&account, false)).unwrap(),
+            r#"{"client":1,"available":"1.5","held":"2.5","total":"4","locked":false}"#
+        );
+    }
+
+    #[test]
+    fn statement_has_running_balance_per_transaction() {
+        let mut account = Account::new(3);
+        account.record_history();
+        for (r#type, tx, amount) in [
+            (TransactionType::Deposit, 1, Some(Decimal::from(10))),
+            (TransactionType::Withdrawal, 2, Some(Decimal::new(25, 1))),
+            (TransactionType::Dispute, 1, None),
+        ] {
+            account
+                .process_transaction(Transaction::new(r#type, 3, tx, amount))
+                .unwrap();
+        }
+        let mut buffer = Vec::new();
+        let mut writer = RecordWriter::new(Format::Csv, &mut buffer);
+        for record in StatementRecord::of(&account) {
+            writer.write(&record).unwrap();
+        }
+        writer.finish().unwrap();
+        assert_eq!(
+            String::from_utf8(buffer).unwrap(),
+            "client,sequence,tx,type,timestamp,currency,amount,available,held,total\n\
+             3,1,1,deposit,,,10,10,0,10\n\
+             3,2,2,withdrawal,,,2.5,7.5,0,7.5\n\
+             3,3,1,dispute,,,10,-2.5,10,7.5\n"
+        );
+    }
+
+    #[test]
+    fn statement_opens_with_the_balance_carried_over() {
+        let mut account = Account::new(3);
+        account
+            .process_transaction(Transaction::new(
+                TransactionType::Deposit,
+                3,
+                1,
+                Some(Decimal::from(10)),
+            ))
+            .unwrap();
+        account.record_history();
+        account
+            .process_transaction(Transaction::new(
+                TransactionType::Withdrawal,
+                3,
+                2,
+                Some(Decimal::from(4)),
+            ))
+            .unwrap();
+        let mut buffer = Vec::new();
+        let mut writer = RecordWriter::new(Format::Csv, &mut buffer);
+        for record in StatementRecord::of(&account) {
+            writer.write(&record).unwrap();
+        }
+        writer.finish().unwrap();
+        assert_eq!(
+            String::from_utf8(buffer).unwrap(),
+            "client,sequence,tx,type,timestamp,currency,amount,available,held,total\n\
+             3,1,,opening,,,10,10,0,10\n\
+             3,2,2,withdrawal,,,4,6,0,6\n"
+        );
+    }
+
+    #[test]
+    fn json_without_records_is_an_empty_array() {
+        assert_eq!(write_all(Format::Json, &[]), "[]\n");
+    }
+
+    #[test]
+    fn jsonl_error_log_has_one_object_per_line() {
+        assert_eq!(
+            write_all(Format::Jsonl, &[make_record(), make_record()]),
+            "{\"input\":\"day1.csv\",\"line\":3,\"client\":1,\"tx\":7,\"type\":\"withdrawal\",\
+             \"timestamp\":null,\"currency\":null,\"reference\":\"INV-1\",\"memo\":null,\
+             \"error_code\":\"insufficient_funds\",\"message\":\"insufficient funds\"}\n"
+                .repeat(2)
+        );
+    }
+}
@@ -0,0 +1,224 @@
+use std::str::FromStr;
+
+use crate::model::{TransactionKind, TransactionRecord};
+
+/// Length of a day in seconds, the unit of timestamps
+pub const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// Which stored transactions may be referenced by a dispute.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum DisputePolicy {
+    /// Only deposits can be disputed
+    #[default]
+    DepositsOnly,
+
+    /// Deposits and withdrawals can be disputed
+    DepositsAndWithdrawals,
+}
+
+impl DisputePolicy {
+    /// Whether a transaction of the given kind can be disputed
+    pub fn allows(&self, kind: TransactionKind) -> bool {
+        match kind {
+            TransactionKind::Deposit => true,
+            TransactionKind::Withdrawal => *self == Self::DepositsAndWithdrawals,
+        }
+    }
+}
+
+/// How long a stored transaction can be disputed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DisputeWindow {
+    /// Within this many subsequent accepted transactions of the account
+    Transactions(u64),
+
+    /// Less than this many days after the transaction, by timestamp
+    Days(u64),
+
+    /// Less than this many days after a transaction with timestamp, and within this many
+    /// subsequent accepted transactions of the account after one without
+    DaysOrTransactions(u64, u64),
+}
+
+/// Point the dispute window of a stored transaction is measured from.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, serde::Serialize, serde::Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum WindowStart {
+    /// Account sequence the transaction was accepted at
+    Sequence(u64),
+
+    /// Timestamp of the transaction
+    Timestamp(u64),
+}
+
+impl DisputeWindow {
+    /// Length of the window in transactions, if it counts them
+    fn transactions(&self) -> Option<u64> {
+        match self {
+            Self::Transactions(transactions) | Self::DaysOrTransactions(_, transactions) => {
+                Some(*transactions)
+            }
+            Self::Days(_) => None,
+        }
+    }
+
+    /// Length of the window in seconds, if it measures time
+    fn seconds(&self) -> Option<u64> {
+        match self {
+            Self::Days(days) | Self::DaysOrTransactions(days, _) => {
+                Some(days.saturating_mul(SECONDS_PER_DAY))
+            }
+            Self::Transactions(_) => None,
+        }
+    }
+
+    /// Point a stored transaction is measured from: its timestamp when the window measures
+    /// time and the transaction has one, its sequence otherwise.
+    ///
+    /// Returns `None` for a transaction without timestamp under a window in days only, which
+    /// never expires.
+    pub fn start(&self, record: &TransactionRecord) -> Option<WindowStart> {
+        record
+            .timestamp
+            .filter(|_| self.seconds().is_some())
+            .map(WindowStart::Timestamp)
+            .or_else(|| {
+                self.transactions()
+                    .map(|_| WindowStart::Sequence(record.sequence))
+            })
+    }
+
+    /// Whether `windows` full windows passed since `start`, for an account at `sequence`
+    /// whose latest timestamp is `clock`
+    pub fn has_passed(
+        &self,
+        start: WindowStart,
+        sequence: u64,
+        clock: Option<u64>,
+        windows: u64,
+    ) -> bool {
+        let (len, start, now) = match start {
+            WindowStart::Sequence(start) => (self.transactions(), start, Some(sequence)),
+            WindowStart::Timestamp(start) => (self.seconds(), start, clock),
+        };
+        match (len, now) {
+            (Some(len), Some(now)) => now.saturating_sub(start) >= len.saturating_mul(windows),
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for DisputeWindow {
+    type Err = String;
+
+    /// Parses `<n>` as a number of transactions, `<n>d` as a number of days and `<n>d,<m>`
+    /// as `n` days with a fallback of `m` transactions
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let parse = |digits: &str| match digits.parse::<u64>() {
+            Ok(0) => Err("dispute window must not be empty".to_string()),
+            Ok(len) => Ok(len),
+            Err(err) => Err(format!("invalid dispute window '{value}': {err}")),
+        };
+        match value.split_once(',') {
+            Some((days, transactions)) => match days.strip_suffix('d') {
+                Some(days) => Ok(Self::DaysOrTransactions(parse(days)?, parse(transactions)?)),
+                None => Err(format!(
+                    "invalid dispute window '{value}': expected <days>d,<transactions>"
+                )),
+            },
+            None => match value.strip_suffix('d') {
+                Some(days) => parse(days).map(Self::Days),
+                None => parse(value).map(Self::Transactions),
+            },
+        }
+    }
+}
+
+/// Deployment-wide settings applied to every account of a ledger.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Policy {
+    pub disputes: DisputePolicy,
+
+    /// How long stored transactions can be disputed, or `None` to keep them forever
+    pub dispute_window: Option<DisputeWindow>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deposits_only_rejects_withdrawals() {
+        let policy = DisputePolicy::DepositsOnly;
+        assert!(policy.allows(TransactionKind::Deposit));
+        assert!(!policy.allows(TransactionKind::Withdrawal));
+    }
+
+    #[test]
+    fn deposits_and_withdrawals_allows_both() {
+        let policy = DisputePolicy::DepositsAndWithdrawals;
+        assert!(policy.allows(TransactionKind::Deposit));
+        assert!(policy.allows(TransactionKind::Withdrawal));
+    }
+
+    #[test]
+    fn dispute_window_parses_transactions_and_days() {
+        assert_eq!("10".parse(), Ok(DisputeWindow::Transactions(10)));
+        assert_eq!("30d".parse(), Ok(DisputeWindow::Days(30)));
+        assert!("0".parse::<DisputeWindow>().is_err());
+        assert!("0d".parse::<DisputeWindow>().is_err());
+        assert!("ten".parse::<DisputeWindow>().is_err());
+        assert!("10h".parse::<DisputeWindow>().is_err());
+        assert_eq!(
+            "30d,1000".parse(),
+            Ok(DisputeWindow::DaysOrTransactions(30, 1000))
+        );
+        assert!("30,1000".parse::<DisputeWindow>().is_err());
+        assert!("30d,0".parse::<DisputeWindow>().is_err());
+        assert!("30d,1000d".parse::<DisputeWindow>().is_err());
+    }
+
+    #[test]
+    fn dispute_window_measures_its_own_unit() {
+        let mut record = TransactionRecord::new(TransactionKind::Deposit, Default::default());
+        record.sequence = 5;
+        let sequence = Some(WindowStart::Sequence(5));
+        assert_eq!(DisputeWindow::Transactions(2).start(&record), sequence);
+        assert_eq!(DisputeWindow::Days(2).start(&record), None);
+        assert_eq!(
+            DisputeWindow::DaysOrTransactions(2, 3).start(&record),
+            sequence
+        );
+        record.timestamp = Some(1_000);
+        let timestamp = Some(WindowStart::Timestamp(1_000));
+        assert_eq!(DisputeWindow::Days(2).start(&record), timestamp);
+        assert_eq!(
+            DisputeWindow::DaysOrTransactions(2, 3).start(&record),
+            timestamp
+        );
+        assert_eq!(DisputeWindow::Transactions(2).start(&record), sequence);
+
+        let window = DisputeWindow::Days(1);
+        let start = WindowStart::Timestamp(1_000);
+        assert!(!window.has_passed(start, 0, Some(1_000 + 86_399), 1));
+        assert!(window.has_passed(start, 0, Some(1_000 + 86_400), 1));
+        assert!(!window.has_passed(start, 0, Some(1_000 + 86_400), 2));
+        assert!(!window.has_passed(start, 0, None, 1));
+        let transactions = DisputeWindow::Transactions(3);
+        assert!(!transactions.has_passed(WindowStart::Sequence(10), 5, None, 1));
+        assert!(transactions.has_passed(WindowStart::Sequence(10), 13, None, 1));
+    }
+
+    #[test]
+    fn mixed_window_counts_transactions_without_timestamp() {
+        let window = DisputeWindow::DaysOrTransactions(1, 3);
+        let sequence = WindowStart::Sequence(10);
+        assert!(!window.has_passed(sequence, 12, Some(u64::MAX), 1));
+        assert!(window.has_passed(sequence, 13, None, 1));
+        let timestamp = WindowStart::Timestamp(1_000);
+        assert!(!window.has_passed(timestamp, u64::MAX, Some(1_000), 1));
+        assert!(window.has_passed(timestamp, 0, Some(1_000 + 86_400), 1));
+    }
+}
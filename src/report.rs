@@ -0,0 +1,287 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufReader, Read},
+    path::Path,
+};
+
+use rust_decimal::{Decimal, RoundingStrategy};
+use serde::{Deserialize, Serialize};
+
+use crate::model::Account;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ReportError {
+    #[error("invalid rate for {0}: rates must be positive")]
+    InvalidRate(String),
+
+    #[error("no rate for currency {0}")]
+    MissingRate(String),
+
+    #[error("converted total of client {0} overflows")]
+    Overflow(u16),
+
+    #[error(transparent)]
+    Csv(#[from] csv::Error),
+
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+/// How converted totals are rounded to the report precision.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Rounding {
+    /// Round to the nearest, ties to the even neighbour (banker's rounding)
+    #[default]
+    HalfEven,
+
+    /// Round to the nearest, ties away from zero
+    HalfUp,
+
+    /// Round to the nearest, ties towards zero
+    HalfDown,
+
+    /// Drop the extra digits
+    Truncate,
+}
+
+impl From<Rounding> for RoundingStrategy {
+    fn from(rounding: Rounding) -> Self {
+        match rounding {
+            Rounding::HalfEven => Self::MidpointNearestEven,
+            Rounding::HalfUp => Self::MidpointAwayFromZero,
+            Rounding::HalfDown => Self::MidpointTowardZero,
+            Rounding::Truncate => Self::ToZero,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct RateRow {
+    currency: String,
+    rate: Decimal,
+}
+
+/// Exchange rates, as the value of one unit of each currency in a common base currency.
+///
+/// Any currency of the table can serve as base: converting between two currencies only uses
+/// the ratio of their rates.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Rates {
+    rates: HashMap<String, Decimal>,
+}
+
+impl Rates {
+    /// Read a `currency,rate` CSV table
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ReportError::InvalidRate`] for a rate that is zero or negative.
+    pub fn from_reader<R: Read>(reader: R) -> Result<Self, ReportError> {
+        let mut rates = HashMap::new();
+        for row in csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(reader)
+            .deserialize()
+        {
+            let RateRow { currency, rate } = row?;
+            if rate <= Decimal::ZERO {
+                return Err(ReportError::InvalidRate(currency));
+            }
+            rates.insert(currency, rate);
+        }
+        Ok(Self { rates })
+    }
+
+    /// Read the rates table in a file
+    pub fn load(path: &Path) -> Result<Self, ReportError> {
+        Self::from_reader(BufReader::new(File::open(path)?))
+    }
+
+    fn rate(&self, currency: &str) -> Result<Decimal, ReportError> {
+        self.rates
+            .get(currency)
+            .copied()
+            .ok_or_else(|| ReportError::MissingRate(currency.to_string()))
+    }
+
+    /// Convert an amount between two currencies, without rounding.
+    ///
+    /// Returns `None` if the conversion overflows.
+    pub fn convert(
+        &self,
+        amount: Decimal,
+        from: &str,
+        to: &str,
+    ) -> Result<Option<Decimal>, ReportError> {
+        if from == to {
+            return Ok(Some(amount));
+        }
+        let (from, to) = (self.rate(from)?, self.rate(to)?);
+        Ok(amount
+            .checked_mul(from)
+            .and_then(|amount| amount.checked_div(to)))
+    }
+}
+
+/// Settings of the consolidated report.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Report {
+    pub rates: Rates,
+
+    /// Currency every total is converted to; balances without currency are assumed to be in
+    /// it already
+    pub currency: String,
+    pub rounding: Rounding,
+
+    /// Decimal places of the converted totals
+    pub scale: u32,
+}
+
+impl Report {
+    /// Consolidated total of an account.
+    ///
+    /// Balances are converted at full precision and summed, and only the sum is rounded, so
+    /// the result does not depend on how many currencies the account holds.
+    pub fn record(&self, account: &Account) -> Result<ReportRecord, ReportError> {
+        let mut total = Decimal::ZERO;
+        for (currency, balance) in account.currency_balances() {
+            let currency = currency.unwrap_or(&self.currency);
+            total = self
+                .rates
+                .convert(balance.total(), currency, &self.currency)?
+                .and_then(|converted| total.checked_add(converted))
+                .ok_or(ReportError::Overflow(account.client))?;
+        }
+        let total = total.round_dp_with_strategy(self.scale, self.rounding.into());
+        Ok(ReportRecord {
+            client: account.client,
+            currency: self.currency.clone(),
+            total: total.normalize().to_string(),
+            locked: account.frozen,
+        })
+    }
+}
+
+/// Total of an account converted to the reporting currency, as written to the report
+#[derive(Debug, PartialEq, Serialize)]
+pub struct ReportRecord {
+    pub client: u16,
+    pub currency: String,
+    pub total: String,
+    pub locked: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_rates() -> Rates {
+        Rates::from_reader("currency,rate\nUSD,1\nEUR, 1.08\nJPY,0.0067\n".as_bytes()).unwrap()
+    }
+
+    fn make_report(rounding: Rounding, scale: u32) -> Report {
+        Report {
+            rates: make_rates(),
+            currency: "USD".to_string(),
+            rounding,
+            scale,
+        }
+    }
+
+    #[test]
+    fn rates_are_read_from_csv() {
+        let rates = make_rates();
+        assert_eq!(rates.rate("EUR").unwrap(), Decimal::new(108, 2));
+        assert!(matches!(rates.rate("GBP"), Err(ReportError::MissingRate(c)) if c == "GBP"));
+    }
+
+    #[test]
+    fn non_positive_rates_are_rejected() {
+        for rate in ["0", "-1.5"] {
+            let table = format!("currency,rate\nEUR,{rate}\n");
+            assert!(matches!(
+                Rates::from_reader(table.as_bytes()),
+                Err(ReportError::InvalidRate(c)) if c == "EUR"
+            ));
+        }
+        assert!(matches!(
+            Rates::from_reader("currency,rate\nEUR,abc\n".as_bytes()),
+            Err(ReportError::Csv(_))
+        ));
+    }
+
+    #[test]
+    fn convert_uses_ratio_of_rates() {
+        let rates = make_rates();
+        assert_eq!(
+            rates.convert(Decimal::from(100), "EUR", "USD").unwrap(),
+            Some(Decimal::from(108))
+        );
+        assert_eq!(
+            rates.convert(Decimal::from(108), "USD", "EUR").unwrap(),
+            Some(Decimal::from(100))
+        );
+        // same currency needs no rate
+        assert_eq!(
+            rates.convert(Decimal::ONE, "GBP", "GBP").unwrap(),
+            Some(Decimal::ONE)
+        );
+        assert_eq!(rates.convert(Decimal::MAX, "EUR", "USD").unwrap(), None);
+    }
+
+    #[test]
+    fn record_sums_every_currency() {
+        let mut account = Account::new(1);
+        account.balance_mut(None).available = Decimal::from(10);
+        account.balance_mut(Some("EUR")).held = Decimal::from(100);
+        account.balance_mut(Some("JPY")).available = Decimal::from(1000);
+        account.frozen = true;
+        assert_eq!(
+            make_report(Rounding::HalfEven, 2).record(&account).unwrap(),
+            ReportRecord {
+                client: 1,
+                currency: "USD".to_string(),
+                total: "124.7".to_string(),
+                locked: true,
+            }
+        );
+    }
+
+    #[test]
+    fn record_rounds_with_the_configured_strategy() {
+        let mut account = Account::new(1);
+        account.balance_mut(Some("USD")).available = Decimal::new(-12345, 3);
+        let total =
+            |account: &Account, rounding| make_report(rounding, 2).record(account).unwrap().total;
+        assert_eq!(total(&account, Rounding::HalfEven), "-12.34");
+        assert_eq!(total(&account, Rounding::HalfUp), "-12.35");
+        assert_eq!(total(&account, Rounding::HalfDown), "-12.34");
+        assert_eq!(total(&account, Rounding::Truncate), "-12.34");
+        account.balance_mut(Some("USD")).available = Decimal::new(12375, 3);
+        assert_eq!(total(&account, Rounding::HalfEven), "12.38");
+        assert_eq!(total(&account, Rounding::HalfDown), "12.37");
+        assert_eq!(total(&account, Rounding::Truncate), "12.37");
+    }
+
+    #[test]
+    fn record_without_rate_is_an_error() {
+        let mut account = Account::new(1);
+        account.balance_mut(Some("GBP")).available = Decimal::ONE;
+        assert!(matches!(
+            make_report(Rounding::HalfEven, 2).record(&account),
+            Err(ReportError::MissingRate(c)) if c == "GBP"
+        ));
+    }
+
+    #[test]
+    fn record_overflow_is_an_error() {
+        let mut account = Account::new(7);
+        account.balance_mut(Some("USD")).available = Decimal::MAX;
+        account.balance_mut(Some("EUR")).available = Decimal::MAX;
+        assert!(matches!(
+            make_report(Rounding::HalfEven, 2).record(&account),
+            Err(ReportError::Overflow(7))
+        ));
+    }
+}
@@ -1,6 +1,9 @@
 use rust_decimal::Decimal;
 
-use crate::model::Account;
+use crate::{
+    model::{Account, Balance, Transaction, TransactionRecord, TransactionState},
+    store::TxIdSet,
+};
 
 #[derive(Debug, thiserror::Error)]
 pub enum RuleError {
@@ -16,8 +19,53 @@ pub enum RuleError {
     #[error("transaction not being disputed: {0}")]
     TrasactionNotOnDispute(u32),
 
+    #[error("transaction already disputed: {0}")]
+    TransactionAlreadyDisputed(u32),
+
     #[error("missing amount for transaction: {0}")]
     MissingAmount(u32),
+
+    #[error("duplicate transaction: {0}")]
+    DuplicateTransaction(u32),
+
+    #[error("dispute window expired for transaction: {0}")]
+    DisputeWindowExpired(u32),
+
+    #[error("withdrawal above the maximum amount: {0}")]
+    WithdrawalAboveMaximum(u32),
+
+    #[error("daily withdrawal limit exceeded: {0}")]
+    DailyLimitExceeded(u32),
+
+    #[error("too many withdrawals: {0}")]
+    TooManyWithdrawals(u32),
+
+    #[error("amount must be positive for transaction: {0}")]
+    NonPositiveAmount(u32),
+
+    #[error("arithmetic overflow on transaction: {0}")]
+    ArithmeticOverflow(u32),
+}
+
+impl RuleError {
+    /// Stable machine-readable identifier of the violation
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::AccountFrozen => "account_frozen",
+            Self::InsuficientFunds => "insufficient_funds",
+            Self::DepositNotFound(_) => "deposit_not_found",
+            Self::TrasactionNotOnDispute(_) => "transaction_not_on_dispute",
+            Self::TransactionAlreadyDisputed(_) => "transaction_already_disputed",
+            Self::MissingAmount(_) => "missing_amount",
+            Self::DuplicateTransaction(_) => "duplicate_transaction",
+            Self::DisputeWindowExpired(_) => "dispute_window_expired",
+            Self::WithdrawalAboveMaximum(_) => "withdrawal_above_maximum",
+            Self::DailyLimitExceeded(_) => "daily_limit_exceeded",
+            Self::TooManyWithdrawals(_) => "too_many_withdrawals",
+            Self::NonPositiveAmount(_) => "non_positive_amount",
+            Self::ArithmeticOverflow(_) => "arithmetic_overflow",
+        }
+    }
 }
 
 /// Checks that the account is not frozen.
@@ -32,13 +80,24 @@ pub fn check_not_frozen(account: &Account) -> Result<(), RuleError> {
     Ok(())
 }
 
-/// Checks that the account has sufficient available funds for the given amount.
+/// Checks that the account has sufficient available funds in the currency for the amount,
+/// counting its credit line.
 ///
 /// # Errors
 ///
-/// Returns [`RuleError::InsuficientFunds`] if `account.available` is less than `amount`.
-pub fn check_sufficient_funds(account: &Account, amount: Decimal) -> Result<(), RuleError> {
-    if account.available < amount {
+/// Returns [`RuleError::InsuficientFunds`] if the available balance in `currency` plus the
+/// credit limit of the account is less than `amount`.
+pub fn check_sufficient_funds(
+    account: &Account,
+    currency: Option<&str>,
+    amount: Decimal,
+) -> Result<(), RuleError> {
+    // saturating, as a sum beyond the representable range covers any amount
+    let funds = account
+        .balance(currency)
+        .available
+        .saturating_add(account.limits.credit);
+    if funds < amount {
         return Err(RuleError::InsuficientFunds);
     }
     Ok(())
@@ -53,36 +112,223 @@ pub fn require_amount(tx_id: u32, amount: Option<Decimal>) -> Result<Decimal, Ru
     amount.ok_or(RuleError::MissingAmount(tx_id))
 }
 
-/// Finds a deposit transaction by ID and returns its amount.
+/// Checks that the transaction has an amount greater than zero and returns it.
+///
+/// A negative deposit would withdraw funds without any balance check, and a negative
+/// withdrawal would deposit them.
+///
+/// # Errors
+///
+/// Returns [`RuleError::MissingAmount`] if the amount is `None`, and
+/// [`RuleError::NonPositiveAmount`] if it is zero or negative.
+pub fn require_positive_amount(tx_id: u32, amount: Option<Decimal>) -> Result<Decimal, RuleError> {
+    let amount = require_amount(tx_id, amount)?;
+    if amount <= Decimal::ZERO {
+        return Err(RuleError::NonPositiveAmount(tx_id));
+    }
+    Ok(amount)
+}
+
+/// Checks that a transaction that can be disputed under the account policy was found.
+///
+/// # Errors
+///
+/// Returns [`RuleError::DepositNotFound`] if no disputable transaction with the given `tx_id`
+/// exists.
+pub fn require_transaction(
+    tx_id: u32,
+    record: Option<TransactionRecord>,
+) -> Result<TransactionRecord, RuleError> {
+    record.ok_or(RuleError::DepositNotFound(tx_id))
+}
+
+/// Checks that the transaction is under dispute.
+///
+/// # Errors
+///
+/// Returns [`RuleError::TrasactionNotOnDispute`] if the transaction is not disputed.
+pub fn check_dispute_exists(tx_id: u32, record: &TransactionRecord) -> Result<(), RuleError> {
+    if record.state != TransactionState::Disputed {
+        return Err(RuleError::TrasactionNotOnDispute(tx_id));
+    }
+    Ok(())
+}
+
+/// Checks that the transaction has never been disputed, so it can enter a dispute.
+///
+/// # Errors
+///
+/// Returns [`RuleError::TransactionAlreadyDisputed`] if it is disputed, resolved or charged
+/// back.
+pub fn check_not_already_disputed(tx_id: u32, record: &TransactionRecord) -> Result<(), RuleError> {
+    if !record.state.can_transition_to(TransactionState::Disputed) {
+        return Err(RuleError::TransactionAlreadyDisputed(tx_id));
+    }
+    Ok(())
+}
+
+/// Checks that the disputed transaction is still within the account dispute window.
+///
+/// A transaction without timestamp under a window in days never expires.
+///
+/// # Errors
+///
+/// Returns [`RuleError::DisputeWindowExpired`] if the window passed.
+pub fn check_dispute_window(
+    account: &Account,
+    dispute: &Transaction,
+    record: &TransactionRecord,
+) -> Result<(), RuleError> {
+    let Some(window) = account.policy.dispute_window else {
+        return Ok(());
+    };
+    // a back-dated dispute cannot reopen an expired window
+    let clock = account.clock.max(dispute.timestamp);
+    if let Some(start) = window.start(record)
+        && window.has_passed(start, account.sequence, clock, 1)
+    {
+        return Err(RuleError::DisputeWindowExpired(dispute.tx));
+    }
+    Ok(())
+}
+
+/// Checks that a withdrawal does not exceed the maximum single withdrawal of the account.
+///
+/// # Errors
+///
+/// Returns [`RuleError::WithdrawalAboveMaximum`] if `amount` is above the maximum.
+pub fn check_max_withdrawal(
+    account: &Account,
+    withdrawal: &Transaction,
+    amount: Decimal,
+) -> Result<(), RuleError> {
+    if account
+        .limits
+        .velocity
+        .max_withdrawal
+        .is_some_and(|max| amount > max)
+    {
+        return Err(RuleError::WithdrawalAboveMaximum(withdrawal.tx));
+    }
+    Ok(())
+}
+
+/// Checks that a withdrawal keeps the account within its withdrawal caps over the velocity
+/// window, counting only the withdrawals in the same currency.
+///
+/// # Errors
+///
+/// Returns [`RuleError::TooManyWithdrawals`] if the window already holds the maximum number
+/// of withdrawals, and [`RuleError::DailyLimitExceeded`] if `amount` would take the total
+/// withdrawn within the window above the cap.
+pub fn check_withdrawal_velocity(
+    account: &Account,
+    withdrawal: &Transaction,
+    amount: Decimal,
+) -> Result<(), RuleError> {
+    let velocity = account.limits.velocity;
+    if !velocity.has_window() {
+        return Ok(());
+    }
+    let (count, total) =
+        account.recent_withdrawals(withdrawal.timestamp, withdrawal.currency.as_deref());
+    if velocity.daily_count.is_some_and(|max| count >= max) {
+        return Err(RuleError::TooManyWithdrawals(withdrawal.tx));
+    }
+    if velocity
+        .daily_amount
+        .is_some_and(|max| total.saturating_add(amount) > max)
+    {
+        return Err(RuleError::DailyLimitExceeded(withdrawal.tx));
+    }
+    Ok(())
+}
+
+/// Computes a balance moved by the given changes to its available and held funds.
 ///
 /// # Errors
 ///
-/// Returns [`RuleError::DepositNotFound`] if no deposit with the given `tx_id` exists.
-pub fn get_deposit_amount<'a>(
-    account: &'a Account,
-    tx_id: &'a u32,
-) -> Result<&'a Decimal, RuleError> {
-    account
-        .find_deposit(tx_id)
-        .ok_or(RuleError::DepositNotFound(*tx_id))
+/// Returns [`RuleError::ArithmeticOverflow`] if the available, held or total funds of the
+/// result fall outside the range of [`Decimal`].
+pub fn checked_balance(
+    tx_id: u32,
+    balance: Balance,
+    available: Decimal,
+    held: Decimal,
+) -> Result<Balance, RuleError> {
+    let overflow = || RuleError::ArithmeticOverflow(tx_id);
+    let balance = Balance {
+        available: balance
+            .available
+            .checked_add(available)
+            .ok_or_else(overflow)?,
+        held: balance.held.checked_add(held).ok_or_else(overflow)?,
+    };
+    balance
+        .available
+        .checked_add(balance.held)
+        .ok_or_else(overflow)?;
+    Ok(balance)
 }
 
-/// Checks that a dispute exists for the given transaction ID.
+/// Checks that the transaction ID has not been used by a previous transaction.
 ///
 /// # Errors
 ///
-/// Returns [`RuleError::TrasactionNotOnDispute`] if no dispute with the given `tx_id` exists.
-pub fn check_dispute_exists(account: &Account, tx_id: &u32) -> Result<(), RuleError> {
-    let _ = get_deposit_amount(account, tx_id)?;
-    account
-        .has_dispute(tx_id)
-        .ok_or(RuleError::TrasactionNotOnDispute(*tx_id))?;
+/// Returns [`RuleError::DuplicateTransaction`] if `tx_id` is already in `tx_ids`.
+pub fn check_unique_transaction(tx_ids: &TxIdSet, tx_id: u32) -> Result<(), RuleError> {
+    if tx_ids.contains(tx_id) {
+        return Err(RuleError::DuplicateTransaction(tx_id));
+    }
     Ok(())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::{
+        model::TransactionKind,
+        policy::{DisputePolicy, Policy},
+    };
+
+    fn deposit(amount: Decimal) -> TransactionRecord {
+        TransactionRecord::new(TransactionKind::Deposit, amount)
+    }
+
+    mod code_tests {
+        use super::*;
+
+        #[test]
+        fn codes_are_stable() {
+            let cases = [
+                (RuleError::AccountFrozen, "account_frozen"),
+                (RuleError::InsuficientFunds, "insufficient_funds"),
+                (RuleError::DepositNotFound(1), "deposit_not_found"),
+                (
+                    RuleError::TrasactionNotOnDispute(1),
+                    "transaction_not_on_dispute",
+                ),
+                (
+                    RuleError::TransactionAlreadyDisputed(1),
+                    "transaction_already_disputed",
+                ),
+                (RuleError::MissingAmount(1), "missing_amount"),
+                (RuleError::DuplicateTransaction(1), "duplicate_transaction"),
+                (RuleError::DisputeWindowExpired(1), "dispute_window_expired"),
+                (
+                    RuleError::WithdrawalAboveMaximum(1),
+                    "withdrawal_above_maximum",
+                ),
+                (RuleError::DailyLimitExceeded(1), "daily_limit_exceeded"),
+                (RuleError::TooManyWithdrawals(1), "too_many_withdrawals"),
+                (RuleError::NonPositiveAmount(1), "non_positive_amount"),
+                (RuleError::ArithmeticOverflow(1), "arithmetic_overflow"),
+            ];
+            for (err, code) in cases {
+                assert_eq!(err.code(), code);
+            }
+        }
+    }
 
     mod check_not_frozen_tests {
         use super::*;
@@ -110,28 +356,114 @@ mod tests {
         #[test]
         fn sufficient_funds_passes() {
             let mut account = Account::new(1);
-            account.available = Decimal::from(100);
-            assert!(check_sufficient_funds(&account, Decimal::from(50)).is_ok());
+            account.balance_mut(None).available = Decimal::from(100);
+            assert!(check_sufficient_funds(&account, None, Decimal::from(50)).is_ok());
         }
 
         #[test]
         fn exact_funds_passes() {
             let mut account = Account::new(1);
-            account.available = Decimal::from(100);
-            assert!(check_sufficient_funds(&account, Decimal::from(100)).is_ok());
+            account.balance_mut(None).available = Decimal::from(100);
+            assert!(check_sufficient_funds(&account, None, Decimal::from(100)).is_ok());
         }
 
         #[test]
         fn insufficient_funds_returns_error() {
             let mut account = Account::new(1);
-            account.available = Decimal::from(50);
+            account.balance_mut(None).available = Decimal::from(50);
+            assert!(matches!(
+                check_sufficient_funds(&account, None, Decimal::from(100)),
+                Err(RuleError::InsuficientFunds)
+            ));
+        }
+
+        #[test]
+        fn credit_limit_extends_available_funds() {
+            let mut account = Account::new(1);
+            account.balance_mut(None).available = Decimal::from(-20);
+            account.limits.credit = Decimal::from(100);
+            assert!(check_sufficient_funds(&account, None, Decimal::from(80)).is_ok());
             assert!(matches!(
-                check_sufficient_funds(&account, Decimal::from(100)),
+                check_sufficient_funds(&account, None, Decimal::new(8001, 2)),
                 Err(RuleError::InsuficientFunds)
             ));
         }
     }
 
+    mod checked_balance_tests {
+        use super::*;
+
+        fn balance(available: Decimal, held: Decimal) -> Balance {
+            Balance { available, held }
+        }
+
+        #[test]
+        fn changes_are_applied() {
+            assert_eq!(
+                checked_balance(
+                    1,
+                    balance(Decimal::TEN, Decimal::ONE),
+                    Decimal::NEGATIVE_ONE,
+                    Decimal::ONE
+                )
+                .unwrap(),
+                balance(Decimal::from(9), Decimal::TWO)
+            );
+        }
+
+        #[test]
+        fn overflowing_funds_return_error() {
+            let cases = [
+                (
+                    balance(Decimal::MAX, Decimal::ZERO),
+                    Decimal::ONE,
+                    Decimal::ZERO,
+                ),
+                (
+                    balance(Decimal::MIN, Decimal::ZERO),
+                    Decimal::NEGATIVE_ONE,
+                    Decimal::ZERO,
+                ),
+                (
+                    balance(Decimal::ZERO, Decimal::MAX),
+                    Decimal::ZERO,
+                    Decimal::ONE,
+                ),
+                // each amount fits, their total does not
+                (
+                    balance(Decimal::MAX, Decimal::ZERO),
+                    Decimal::ZERO,
+                    Decimal::ONE,
+                ),
+            ];
+            for (balance, available, held) in cases {
+                assert!(matches!(
+                    checked_balance(5, balance, available, held),
+                    Err(RuleError::ArithmeticOverflow(5))
+                ));
+            }
+        }
+    }
+
+    mod check_max_withdrawal_tests {
+        use super::*;
+        use crate::model::TransactionType;
+
+        #[test]
+        fn withdrawal_up_to_maximum_passes() {
+            let mut account = Account::new(1);
+            let withdrawal =
+                Transaction::new(TransactionType::Withdrawal, 1, 9, Some(Decimal::from(50)));
+            assert!(check_max_withdrawal(&account, &withdrawal, Decimal::from(1_000_000)).is_ok());
+            account.limits.velocity.max_withdrawal = Some(Decimal::from(50));
+            assert!(check_max_withdrawal(&account, &withdrawal, Decimal::from(50)).is_ok());
+            assert!(matches!(
+                check_max_withdrawal(&account, &withdrawal, Decimal::new(5001, 2)),
+                Err(RuleError::WithdrawalAboveMaximum(9))
+            ));
+        }
+    }
+
     mod require_amount_tests {
         use super::*;
 
@@ -148,27 +480,86 @@ mod tests {
         }
     }
 
-    mod get_deposit_amount_tests {
+    mod require_positive_amount_tests {
         use super::*;
 
         #[test]
-        fn deposit_found_returns_amount() {
-            let mut account = Account::new(1);
-            account.deposits.insert(1, Decimal::from(100));
+        fn positive_amount_returns_value() {
+            let result = require_positive_amount(1, Some(Decimal::new(1, 4)));
+            assert_eq!(result.unwrap(), Decimal::new(1, 4));
+        }
+
+        #[test]
+        fn zero_and_negative_amounts_return_error() {
+            for amount in [Decimal::ZERO, Decimal::NEGATIVE_ONE, Decimal::new(-1, 4)] {
+                assert!(matches!(
+                    require_positive_amount(3, Some(amount)),
+                    Err(RuleError::NonPositiveAmount(3))
+                ));
+            }
+        }
+
+        #[test]
+        fn amount_missing_returns_error() {
+            assert!(matches!(
+                require_positive_amount(1, None),
+                Err(RuleError::MissingAmount(1))
+            ));
+        }
+    }
+
+    mod require_transaction_tests {
+        use super::*;
+
+        #[test]
+        fn found_transaction_returns_record() {
             assert_eq!(
-                get_deposit_amount(&account, &1).unwrap(),
-                &Decimal::from(100)
+                require_transaction(1, Some(deposit(Decimal::from(100)))).unwrap(),
+                deposit(Decimal::from(100))
             );
         }
 
         #[test]
-        fn deposit_not_found_returns_error() {
-            let account = Account::new(1);
+        fn missing_transaction_returns_error() {
             assert!(matches!(
-                get_deposit_amount(&account, &99),
+                require_transaction(99, None),
                 Err(RuleError::DepositNotFound(99))
             ));
         }
+
+        #[test]
+        fn withdrawal_not_disputable_by_default() {
+            let mut account = Account::new(1);
+            account
+                .transactions
+                .insert(
+                    1,
+                    TransactionRecord::new(TransactionKind::Withdrawal, Decimal::from(100)),
+                )
+                .unwrap();
+            assert!(matches!(
+                require_transaction(1, account.find_transaction(&1).unwrap()),
+                Err(RuleError::DepositNotFound(1))
+            ));
+        }
+
+        #[test]
+        fn withdrawal_found_when_policy_allows() {
+            let mut account = Account::with_policy(
+                1,
+                Policy {
+                    disputes: DisputePolicy::DepositsAndWithdrawals,
+                    ..Default::default()
+                },
+            );
+            let withdrawal =
+                TransactionRecord::new(TransactionKind::Withdrawal, Decimal::from(100));
+            account.transactions.insert(1, withdrawal.clone()).unwrap();
+            assert_eq!(
+                require_transaction(1, account.find_transaction(&1).unwrap()).unwrap(),
+                withdrawal
+            );
+        }
     }
 
     mod check_dispute_exists_tests {
@@ -176,28 +567,173 @@ mod tests {
 
         #[test]
         fn dispute_exists_passes() {
-            let mut account = Account::new(1);
-            account.deposits.insert(1, Decimal::from(100));
-            account.disputes.insert(1);
-            assert!(check_dispute_exists(&account, &1).is_ok());
+            let mut record = deposit(Decimal::from(100));
+            record.state = TransactionState::Disputed;
+            assert!(check_dispute_exists(1, &record).is_ok());
         }
 
         #[test]
-        fn deposit_not_found_returns_error() {
-            let account = Account::new(1);
+        fn dispute_missing_returns_error() {
+            for state in [
+                TransactionState::Processed,
+                TransactionState::Resolved,
+                TransactionState::ChargedBack,
+            ] {
+                let mut record = deposit(Decimal::from(100));
+                record.state = state;
+                assert!(matches!(
+                    check_dispute_exists(1, &record),
+                    Err(RuleError::TrasactionNotOnDispute(1))
+                ));
+            }
+        }
+    }
+
+    mod check_not_already_disputed_tests {
+        use super::*;
+
+        fn deposit_in(state: TransactionState) -> TransactionRecord {
+            let mut record = deposit(Decimal::from(100));
+            record.state = state;
+            record
+        }
+
+        #[test]
+        fn processed_deposit_passes() {
+            let record = deposit_in(TransactionState::Processed);
+            assert!(check_not_already_disputed(1, &record).is_ok());
+        }
+
+        #[test]
+        fn disputed_deposit_returns_error() {
+            let record = deposit_in(TransactionState::Disputed);
             assert!(matches!(
-                check_dispute_exists(&account, &1),
-                Err(RuleError::DepositNotFound(1))
+                check_not_already_disputed(1, &record),
+                Err(RuleError::TransactionAlreadyDisputed(1))
             ));
         }
 
         #[test]
-        fn dispute_missing_returns_error() {
+        fn settled_deposit_returns_error() {
+            for state in [TransactionState::Resolved, TransactionState::ChargedBack] {
+                let record = deposit_in(state);
+                assert!(matches!(
+                    check_not_already_disputed(1, &record),
+                    Err(RuleError::TransactionAlreadyDisputed(1))
+                ));
+            }
+        }
+    }
+
+    mod check_dispute_window_tests {
+        use super::*;
+        use crate::{model::TransactionType, policy::DisputeWindow};
+
+        fn account_with_window(window: DisputeWindow) -> Account {
+            Account::with_policy(
+                1,
+                Policy {
+                    dispute_window: Some(window),
+                    ..Default::default()
+                },
+            )
+        }
+
+        fn dispute_at(timestamp: Option<u64>) -> Transaction {
+            Transaction {
+                timestamp,
+                ..Transaction::new(TransactionType::Dispute, 1, 1, None)
+            }
+        }
+
+        fn deposit_at(sequence: u64, timestamp: Option<u64>) -> TransactionRecord {
+            let mut record = deposit(Decimal::from(100));
+            record.sequence = sequence;
+            record.timestamp = timestamp;
+            record
+        }
+
+        #[test]
+        fn no_window_passes() {
             let mut account = Account::new(1);
-            account.deposits.insert(1, Decimal::from(100));
+            account.sequence = u64::MAX;
+            assert!(
+                check_dispute_window(&account, &dispute_at(None), &deposit_at(1, None)).is_ok()
+            );
+        }
+
+        #[test]
+        fn transactions_window_counts_subsequent_transactions() {
+            let mut account = account_with_window(DisputeWindow::Transactions(2));
+            let record = deposit_at(1, None);
+            account.sequence = 2;
+            assert!(check_dispute_window(&account, &dispute_at(None), &record).is_ok());
+            account.sequence = 3;
+            assert!(matches!(
+                check_dispute_window(&account, &dispute_at(None), &record),
+                Err(RuleError::DisputeWindowExpired(1))
+            ));
+        }
+
+        #[test]
+        fn days_window_compares_timestamps() {
+            let account = account_with_window(DisputeWindow::Days(1));
+            let record = deposit_at(1, Some(0));
+            assert!(check_dispute_window(&account, &dispute_at(Some(86_399)), &record).is_ok());
+            assert!(matches!(
+                check_dispute_window(&account, &dispute_at(Some(86_400)), &record),
+                Err(RuleError::DisputeWindowExpired(1))
+            ));
+        }
+
+        #[test]
+        fn days_window_falls_back_to_account_clock() {
+            let mut account = account_with_window(DisputeWindow::Days(1));
+            let record = deposit_at(1, Some(0));
+            assert!(check_dispute_window(&account, &dispute_at(None), &record).is_ok());
+            account.clock = Some(90_000);
+            assert!(matches!(
+                check_dispute_window(&account, &dispute_at(None), &record),
+                Err(RuleError::DisputeWindowExpired(1))
+            ));
+        }
+
+        #[test]
+        fn days_window_ignores_back_dated_dispute() {
+            let mut account = account_with_window(DisputeWindow::Days(30));
+            let record = deposit_at(1, Some(0));
+            account.clock = Some(31 * 86_400);
+            assert!(matches!(
+                check_dispute_window(&account, &dispute_at(Some(1)), &record),
+                Err(RuleError::DisputeWindowExpired(1))
+            ));
+        }
+
+        #[test]
+        fn days_window_ignores_transactions_without_timestamp() {
+            let account = account_with_window(DisputeWindow::Days(1));
+            assert!(
+                check_dispute_window(&account, &dispute_at(Some(u64::MAX)), &deposit_at(1, None))
+                    .is_ok()
+            );
+        }
+    }
+
+    mod check_unique_transaction_tests {
+        use super::*;
+
+        #[test]
+        fn new_transaction_passes() {
+            let tx_ids = TxIdSet::from_iter([1, 2]);
+            assert!(check_unique_transaction(&tx_ids, 3).is_ok());
+        }
+
+        #[test]
+        fn known_transaction_returns_error() {
+            let tx_ids = TxIdSet::from_iter([1, 2]);
             assert!(matches!(
-                check_dispute_exists(&account, &1),
-                Err(RuleError::TrasactionNotOnDispute(1))
+                check_unique_transaction(&tx_ids, 2),
+                Err(RuleError::DuplicateTransaction(2))
             ));
         }
     }
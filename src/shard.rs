@@ -0,0 +1,366 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    sync::{
+        Arc,
+        mpsc::{Receiver, Sender, SyncSender, channel, sync_channel},
+    },
+    thread::{self, JoinHandle},
+};
+
+use crate::{
+    Ledger,
+    model::{AccountError, Transaction},
+    output::ErrorRecord,
+    store::StoreError,
+};
+
+/// Number of transactions handed to a worker at once
+const BATCH_SIZE: usize = 256;
+
+/// Number of batches a worker can lag behind the reader before the reader blocks
+const QUEUE_BOUND: usize = 64;
+
+/// Transactions with their position in the stream, and the input and line they were read at
+type Batch = Vec<(u64, Arc<str>, u64, Transaction)>;
+
+/// Outcome of a worker: its shard and the store failure that stopped it, if any
+type Outcome = (Ledger, Option<StoreError>);
+
+/// Transactions a worker rejected in a batch, sent back once the batch is processed
+type Processed = (usize, Vec<(u64, ErrorRecord)>);
+
+/// Shard owning the accounts of a disjoint set of clients
+struct Worker {
+    sender: SyncSender<Batch>,
+    handle: JoinHandle<Outcome>,
+    batch: Batch,
+
+    /// First positions of the batches sent to the worker and not processed yet, oldest first
+    in_flight: VecDeque<u64>,
+}
+
+impl Worker {
+    fn spawn(index: usize, mut shard: Ledger, results: Sender<Processed>) -> Self {
+        let (sender, receiver) = sync_channel::<Batch>(QUEUE_BOUND);
+        let handle = thread::spawn(move || {
+            for batch in receiver {
+                let mut errors = Vec::new();
+                for (position, input, line, tx) in batch {
+                    match shard.apply_registered(tx.clone()) {
+                        Ok(()) => {}
+                        Err(AccountError::Store(err)) => return (shard, Some(err)),
+                        Err(err) => {
+                            errors.push((position, ErrorRecord::new(&input, line, &tx, &err)))
+                        }
+                    }
+                }
+                let _ = results.send((index, errors));
+            }
+            (shard, None)
+        });
+        Self {
+            sender,
+            handle,
+            batch: Vec::with_capacity(BATCH_SIZE),
+            in_flight: VecDeque::new(),
+        }
+    }
+
+    fn flush(&mut self) {
+        let Some(&(position, ..)) = self.batch.first() else {
+            return;
+        };
+        let batch = std::mem::replace(&mut self.batch, Vec::with_capacity(BATCH_SIZE));
+        self.in_flight.push_back(position);
+        // a failed send means the worker stopped, which surfaces when it is joined
+        let _ = self.sender.send(batch);
+    }
+
+    /// Position of the oldest transaction routed to the worker and not processed yet
+    fn oldest_pending(&self) -> Option<u64> {
+        self.in_flight
+            .front()
+            .copied()
+            .or_else(|| self.batch.first().map(|&(position, ..)| position))
+    }
+}
+
+/// Processes transactions on worker threads, each owning the accounts of a shard of clients.
+///
+/// Transactions of a client always go to the same worker, in input order, and transaction
+/// IDs are registered on the calling thread in input order too. Therefore the resulting
+/// ledger and the reported errors are identical to applying the same stream serially with
+/// [`Ledger::apply`].
+///
+/// Rejected transactions are handed out by [`ShardedLedger::ready`] as soon as every earlier
+/// transaction is processed, so only the ones still waiting on a worker are kept in memory.
+pub struct ShardedLedger {
+    ledger: Ledger,
+    workers: Vec<Worker>,
+    results: Receiver<Processed>,
+
+    /// Rejected transactions waiting for the earlier ones to be processed, by position
+    errors: BTreeMap<u64, ErrorRecord>,
+    position: u64,
+}
+
+impl ShardedLedger {
+    /// Distribute the accounts of `ledger` over `threads` workers
+    pub fn new(mut ledger: Ledger, threads: usize) -> Self {
+        let threads = threads.max(1);
+        let mut shards: Vec<Ledger> = (0..threads)
+            .map(|_| {
+                let mut shard = Ledger::with_storage(ledger.policy, ledger.storage.clone());
+                shard.limits = ledger.limits.clone();
+                shard.histories = ledger.histories;
+                shard
+            })
+            .collect();
+        for (client, account) in ledger.accounts.drain() {
+            shards[usize::from(client) % threads]
+                .accounts
+                .insert(client, account);
+        }
+
+        let (sender, results) = channel();
+        let workers = shards
+            .into_iter()
+            .enumerate()
+            .map(|(index, shard)| Worker::spawn(index, shard, sender.clone()))
+            .collect();
+        Self {
+            ledger,
+            workers,
+            results,
+            errors: BTreeMap::new(),
+            position: 0,
+        }
+    }
+
+    /// Route a transaction read at `line` of `input` to the worker owning its client
+    pub fn apply(&mut self, input: &Arc<str>, line: u64, tx: Transaction) {
+        let position = self.position;
+        self.position += 1;
+
+        let shards = self.workers.len();
+        // a worker that seldom gets a transaction must not hold back the errors of others
+        if self.position.is_multiple_of((BATCH_SIZE * shards) as u64) {
+            self.workers.iter_mut().for_each(Worker::flush);
+        }
+
+        if let Err(err) = self.ledger.register(&tx) {
+            self.errors
+                .insert(position, ErrorRecord::new(input, line, &tx, &err.into()));
+            return;
+        }
+
+        let worker = &mut self.workers[usize::from(tx.client) % shards];
+        worker.batch.push((position, Arc::clone(input), line, tx));
+        if worker.batch.len() >= BATCH_SIZE {
+            worker.flush();
+        }
+    }
+
+    /// Collect the batches processed by the workers
+    fn collect_results(&mut self) {
+        for (worker, errors) in self.results.try_iter() {
+            self.workers[worker].in_flight.pop_front();
+            self.errors.extend(errors);
+        }
+    }
+
+    /// Rejected transactions all of whose earlier transactions are processed, in input order
+    pub fn ready(&mut self) -> Vec<ErrorRecord> {
+        self.collect_results();
+        let processed = self
+            .workers
+            .iter()
+            .filter_map(Worker::oldest_pending)
+            .min()
+            .unwrap_or(self.position);
+        let waiting = self.errors.split_off(&processed);
+        std::mem::replace(&mut self.errors, waiting)
+            .into_values()
+            .collect()
+    }
+
+    /// Wait for all workers and merge their accounts back into a single ledger.
+    ///
+    /// Returns the merged ledger along with the rejected transactions not yet handed out by
+    /// [`ShardedLedger::ready`], in input order.
+    ///
+    /// # Errors
+    ///
+    /// Returns the first [`StoreError`] a worker ran into; the ledger is lost in that case.
+    pub fn finish(mut self) -> Result<(Ledger, Vec<ErrorRecord>), StoreError> {
+        let mut failure = None;
+        for mut worker in std::mem::take(&mut self.workers) {
+            worker.flush();
+            drop(worker.sender);
+            let (shard, err) = worker
+                .handle
+                .join()
+                .unwrap_or_else(|panic| std::panic::resume_unwind(panic));
+            self.ledger.accounts.extend(shard.accounts);
+            self.ledger.sequence += shard.sequence;
+            failure = failure.or(err);
+        }
+        if let Some(err) = failure {
+            return Err(err);
+        }
+
+        for (_, errors) in self.results.try_iter() {
+            self.errors.extend(errors);
+        }
+        Ok((self.ledger, self.errors.into_values().collect()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::TransactionType;
+    use rust_decimal::Decimal;
+
+    /// Deterministic stream mixing every transaction type, valid or not, over a few clients
+    fn make_stream(len: u32) -> Vec<Transaction> {
+        let mut seed: u64 = 42;
+        let mut next = move |bound: u64| {
+            seed = seed
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (seed >> 33) % bound
+        };
+        (0..len)
+            .map(|i| {
+                let r#type = match next(10) {
+                    0..=3 => TransactionType::Deposit,
+                    4..=5 => TransactionType::Withdrawal,
+                    6..=7 => TransactionType::Dispute,
+                    8 => TransactionType::Resolve,
+                    _ => TransactionType::Chargeback,
+                };
+                let tx = match r#type {
+                    // reuse some IDs to exercise the registry
+                    TransactionType::Deposit | TransactionType::Withdrawal if next(20) == 0 => {
+                        next(u64::from(i) + 1) as u32
+                    }
+                    TransactionType::Deposit | TransactionType::Withdrawal => i,
+                    _ => next(u64::from(i) + 1) as u32,
+                };
+                Transaction::new(
+                    r#type,
+                    next(17) as u16,
+                    tx,
+                    Some(Decimal::new(next(100_000) as i64, 2)),
+                )
+            })
+            .collect()
+    }
+
+    fn apply_serially(stream: &[Transaction]) -> (Ledger, Vec<ErrorRecord>) {
+        let mut ledger = Ledger::new();
+        let mut errors = Vec::new();
+        for (line, tx) in stream.iter().cloned().enumerate() {
+            if let Err(err) = ledger.apply(tx.clone()) {
+                errors.push(ErrorRecord::new("stream", line as u64, &tx, &err));
+            }
+        }
+        (ledger, errors)
+    }
+
+    fn apply_sharded(
+        ledger: Ledger,
+        stream: &[Transaction],
+        threads: usize,
+    ) -> (Ledger, Vec<ErrorRecord>) {
+        let mut sharded = ShardedLedger::new(ledger, threads);
+        let input: Arc<str> = Arc::from("stream");
+        let mut errors = Vec::new();
+        for (line, tx) in stream.iter().cloned().enumerate() {
+            sharded.apply(&input, line as u64, tx);
+            errors.extend(sharded.ready());
+        }
+        let (ledger, rest) = sharded.finish().unwrap();
+        errors.extend(rest);
+        (ledger, errors)
+    }
+
+    #[test]
+    fn sharded_matches_serial() {
+        let stream = make_stream(5_000);
+        let (serial, serial_errors) = apply_serially(&stream);
+        assert!(!serial_errors.is_empty());
+        for threads in [1, 2, 3, 8] {
+            let (sharded, sharded_errors) = apply_sharded(Ledger::new(), &stream, threads);
+            assert_eq!(sharded.snapshot(), serial.snapshot(), "{threads} threads");
+            assert_eq!(sharded.sequence(), serial.sequence(), "{threads} threads");
+            assert_eq!(sharded_errors, serial_errors, "{threads} threads");
+        }
+    }
+
+    #[test]
+    fn sharded_resumes_existing_accounts() {
+        let stream = make_stream(2_000);
+        let (head, tail) = stream.split_at(1_000);
+        let (serial, _) = apply_serially(&stream);
+        let (resumed, _) = apply_serially(head);
+        let (sharded, _) = apply_sharded(resumed, tail, 4);
+        assert_eq!(sharded.snapshot(), serial.snapshot());
+    }
+
+    #[test]
+    fn sharded_disk_store_with_window_matches_serial() {
+        use crate::{
+            policy::Policy,
+            store::{DiskFile, Storage},
+        };
+
+        let stream = make_stream(3_000);
+        let policy = Policy {
+            dispute_window: Some(crate::policy::DisputeWindow::Transactions(20)),
+            ..Default::default()
+        };
+        let mut serial = Ledger::with_policy(policy);
+        for tx in stream.iter().cloned() {
+            let _ = serial.apply(tx);
+        }
+
+        let path = std::env::temp_dir().join(format!("tx-cli-shard-{}", std::process::id()));
+        let storage = Storage::Disk(DiskFile::create(&path).unwrap());
+        let (sharded, _) = apply_sharded(Ledger::with_storage(policy, storage), &stream, 4);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(sharded.snapshot(), serial.snapshot());
+    }
+
+    #[test]
+    fn errors_are_handed_out_before_the_end() {
+        let stream = make_stream(5_000);
+        let (_, serial_errors) = apply_serially(&stream);
+        let mut sharded = ShardedLedger::new(Ledger::new(), 4);
+        let input: Arc<str> = Arc::from("stream");
+        for (line, tx) in stream.iter().cloned().enumerate() {
+            sharded.apply(&input, line as u64, tx);
+        }
+        // every batch but the last partial ones is sent, so most errors come out once the
+        // workers catch up
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(30);
+        let mut ready = Vec::new();
+        while ready.len() < serial_errors.len() / 2 && std::time::Instant::now() < deadline {
+            ready.extend(sharded.ready());
+            thread::yield_now();
+        }
+        assert!(ready.len() >= serial_errors.len() / 2);
+        assert_eq!(ready, serial_errors[..ready.len()]);
+        let (_, rest) = sharded.finish().unwrap();
+        ready.extend(rest);
+        assert_eq!(ready, serial_errors);
+    }
+
+    #[test]
+    fn empty_stream_keeps_ledger() {
+        let (ledger, errors) = ShardedLedger::new(Ledger::new(), 4).finish().unwrap();
+        assert!(errors.is_empty());
+        assert_eq!(ledger.accounts().count(), 0);
+    }
+}
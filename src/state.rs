@@ -0,0 +1,525 @@
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{BufReader, BufWriter, Read, Write},
+    ops::RangeInclusive,
+    path::Path,
+};
+
+use rust_decimal::Decimal;
+
+use crate::{
+    Ledger,
+    ledger::Histories,
+    limits::WithdrawalEntry,
+    model::{Account, Balance, HistoryEntry, TransactionRecord},
+    policy::{Policy, WindowStart},
+    store::{Storage, StoreError},
+};
+
+/// Version of the on-disk state format written by this build.
+///
+/// Bump it whenever a persisted field is added: an older build reading the state would
+/// otherwise drop the fields it does not know about without notice.
+pub const STATE_VERSION: u32 = 3;
+
+#[derive(Debug, thiserror::Error)]
+pub enum StateError {
+    #[error("unsupported state version {0}, expected {STATE_VERSION}")]
+    UnsupportedVersion(u32),
+
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+
+    #[error(transparent)]
+    Store(#[from] StoreError),
+
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+/// Persisted state of a single account
+#[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct AccountState {
+    pub client: u16,
+    pub available: Decimal,
+    pub held: Decimal,
+    /// Balances of the transactions that carried a currency
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub currencies: BTreeMap<String, Balance>,
+    pub frozen: bool,
+    pub transactions: BTreeMap<u32, TransactionRecord>,
+    #[serde(default)]
+    pub sequence: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub clock: Option<u64>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub expiry: Vec<(WindowStart, u32)>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub withdrawals: Vec<WithdrawalEntry>,
+    /// History of an account recording it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub history: Option<Vec<HistoryEntry>>,
+}
+
+/// Persisted state of a whole ledger.
+///
+/// Accounts and IDs are sorted so that saving the same ledger twice yields the same bytes.
+#[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct LedgerState {
+    pub version: u32,
+    pub accounts: Vec<AccountState>,
+    pub tx_ids: Vec<IdRun>,
+    #[serde(default)]
+    pub sequence: u64,
+}
+
+/// Consecutive transaction IDs, saved as a single number or a `[first, last]` pair
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(untagged)]
+pub enum IdRun {
+    One(u32),
+    Span(u32, u32),
+}
+
+impl From<RangeInclusive<u32>> for IdRun {
+    fn from(range: RangeInclusive<u32>) -> Self {
+        match range.into_inner() {
+            (first, last) if first == last => Self::One(first),
+            (first, last) => Self::Span(first, last),
+        }
+    }
+}
+
+impl From<IdRun> for RangeInclusive<u32> {
+    fn from(run: IdRun) -> Self {
+        match run {
+            IdRun::One(tx_id) => tx_id..=tx_id,
+            IdRun::Span(first, last) => first..=last,
+        }
+    }
+}
+
+/// The version field alone, read before the rest of the state
+#[derive(serde::Deserialize)]
+struct Version {
+    version: u32,
+}
+
+impl TryFrom<&Account> for AccountState {
+    type Error = StoreError;
+
+    fn try_from(account: &Account) -> Result<Self, Self::Error> {
+        Ok(Self {
+            client: account.client,
+            available: account.balance(None).available,
+            held: account.balance(None).held,
+            currencies: account
+                .balances
+                .iter()
+                .filter_map(|(currency, balance)| Some((currency.clone()?, *balance)))
+                .collect(),
+            frozen: account.frozen,
+            transactions: account.transactions.records()?.into_iter().collect(),
+            sequence: account.sequence,
+            clock: account.clock,
+            expiry: account.expiry.iter().copied().collect(),
+            withdrawals: account.withdrawals.iter().cloned().collect(),
+            history: account.history.clone(),
+        })
+    }
+}
+
+impl TryFrom<&Ledger> for LedgerState {
+    type Error = StoreError;
+
+    fn try_from(ledger: &Ledger) -> Result<Self, Self::Error> {
+        let mut accounts = ledger
+            .accounts()
+            .map(AccountState::try_from)
+            .collect::<Result<Vec<_>, _>>()?;
+        accounts.sort_by_key(|account| account.client);
+        let tx_ids = ledger.tx_ids.ranges().map(IdRun::from).collect();
+        Ok(Self {
+            version: STATE_VERSION,
+            accounts,
+            tx_ids,
+            sequence: ledger.sequence,
+        })
+    }
+}
+
+impl LedgerState {
+    /// Rebuild a ledger whose accounts all follow the given policy and storage
+    pub fn into_ledger(self, policy: Policy, storage: Storage) -> Result<Ledger, StoreError> {
+        let mut ledger = Ledger::with_storage(policy, storage);
+        for run in self.tx_ids {
+            ledger.tx_ids.insert_range(run.into());
+        }
+        ledger.sequence = self.sequence;
+        for state in self.accounts {
+            let mut account =
+                Account::with_store(state.client, policy, ledger.storage.open_store());
+            // a zero balance without currency is implied unless it is the only one
+            if state.currencies.is_empty()
+                || state.available != Decimal::ZERO
+                || state.held != Decimal::ZERO
+            {
+                let balance = account.balance_mut(None);
+                balance.available = state.available;
+                balance.held = state.held;
+            }
+            for (currency, balance) in state.currencies {
+                account.balances.insert(Some(currency), balance);
+            }
+            account.frozen = state.frozen;
+            account.sequence = state.sequence;
+            account.clock = state.clock;
+            account.expiry = state.expiry.into_iter().collect();
+            account.withdrawals = state.withdrawals.into();
+            // a saved history keeps being recorded, by the accounts opened later too
+            if state.history.is_some() {
+                ledger.histories = Histories::All;
+            }
+            account.history = state.history;
+            for (tx_id, record) in state.transactions {
+                account.transactions.insert(tx_id, record)?;
+            }
+            ledger.accounts.insert(account.client, account);
+        }
+        Ok(ledger)
+    }
+}
+
+impl Ledger {
+    /// Write the full ledger state as versioned JSON
+    pub fn save_to<W: Write>(&self, writer: W) -> Result<(), StateError> {
+        serde_json::to_writer(writer, &LedgerState::try_from(self)?)?;
+        Ok(())
+    }
+
+    /// Read a ledger state written by [`Ledger::save_to`]
+    ///
+    /// # Errors
+    ///
+    /// Returns [`StateError::UnsupportedVersion`] if the state was written in another format
+    /// version.
+    pub fn load_from<R: Read>(
+        mut reader: R,
+        policy: Policy,
+        storage: Storage,
+    ) -> Result<Self, StateError> {
+        // the version is read from the same bytes first, skipping the rest of the document
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        let Version { version } = serde_json::from_slice(&bytes)?;
+        if version != STATE_VERSION {
+            return Err(StateError::UnsupportedVersion(version));
+        }
+        let state: LedgerState = serde_json::from_slice(&bytes)?;
+        Ok(state.into_ledger(policy, storage)?)
+    }
+
+    /// Save the ledger state to a file.
+    ///
+    /// The state is written to a sibling temporary file first and then renamed over `path`,
+    /// so a crash never leaves a half-written state behind.
+    pub fn save(&self, path: &Path) -> Result<(), StateError> {
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        let file = File::create(&tmp)?;
+        let mut writer = BufWriter::new(file);
+        self.save_to(&mut writer)?;
+        writer.flush()?;
+        writer.get_ref().sync_all()?;
+        std::fs::rename(&tmp, path)?;
+        Ok(())
+    }
+
+    /// Load the ledger state from a file written by [`Ledger::save`]
+    pub fn load(path: &Path, policy: Policy, storage: Storage) -> Result<Self, StateError> {
+        Self::load_from(BufReader::new(File::open(path)?), policy, storage)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{AccountError, Transaction, TransactionState, TransactionType};
+
+    fn make_ledger() -> Ledger {
+        let mut ledger = Ledger::new();
+        let txs = [
+            Transaction::new(TransactionType::Deposit, 1, 1, Some(Decimal::new(15, 1))),
+            Transaction::new(TransactionType::Deposit, 1, 2, Some(Decimal::from(3))),
+            Transaction::new(TransactionType::Dispute, 1, 1, None),
+            Transaction::new(TransactionType::Deposit, 2, 3, Some(Decimal::from(5))),
+            Transaction::new(TransactionType::Dispute, 2, 3, None),
+            Transaction::new(TransactionType::Chargeback, 2, 3, None),
+            Transaction::new(TransactionType::Withdrawal, 1, 4, Some(Decimal::ONE)),
+        ];
+        for tx in txs {
+            ledger.apply(tx).unwrap();
+        }
+        ledger
+    }
+
+    fn round_trip(ledger: &Ledger) -> Ledger {
+        let mut buffer = Vec::new();
+        ledger.save_to(&mut buffer).unwrap();
+        Ledger::load_from(buffer.as_slice(), Policy::default(), Storage::Memory).unwrap()
+    }
+
+    #[test]
+    fn round_trip_preserves_balances() {
+        let ledger = make_ledger();
+        assert_eq!(round_trip(&ledger).snapshot(), ledger.snapshot());
+    }
+
+    #[test]
+    fn round_trip_preserves_open_disputes() {
+        let ledger = round_trip(&make_ledger());
+        let account = ledger.account(1).unwrap();
+        assert!(account.has_dispute(&1).unwrap());
+        assert_eq!(
+            account.find_transaction(&2).unwrap().unwrap().state,
+            TransactionState::Processed
+        );
+        assert_eq!(
+            ledger
+                .account(2)
+                .unwrap()
+                .find_transaction(&3)
+                .unwrap()
+                .unwrap()
+                .state,
+            TransactionState::ChargedBack
+        );
+        assert!(ledger.account(2).unwrap().frozen);
+    }
+
+    #[test]
+    fn resumed_ledger_accepts_disputes_on_earlier_deposits() {
+        let mut ledger = round_trip(&make_ledger());
+        ledger
+            .apply(Transaction::new(TransactionType::Resolve, 1, 1, None))
+            .unwrap();
+        ledger
+            .apply(Transaction::new(TransactionType::Dispute, 1, 2, None))
+            .unwrap();
+        let account = ledger.account(1).unwrap();
+        assert_eq!(account.balance(None).held, Decimal::from(3));
+    }
+
+    #[test]
+    fn resumed_ledger_rejects_known_transaction_ids() {
+        let mut ledger = round_trip(&make_ledger());
+        assert!(
+            ledger
+                .apply(Transaction::new(
+                    TransactionType::Deposit,
+                    3,
+                    4,
+                    Some(Decimal::ONE)
+                ))
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn transaction_ids_are_saved_as_runs() {
+        let mut ledger = Ledger::new();
+        for tx in [1, 2, 3, 5] {
+            ledger
+                .apply(Transaction::new(
+                    TransactionType::Deposit,
+                    1,
+                    tx,
+                    Some(Decimal::ONE),
+                ))
+                .unwrap();
+        }
+        let state = LedgerState::try_from(&ledger).unwrap();
+        assert_eq!(state.tx_ids, vec![IdRun::Span(1, 3), IdRun::One(5)]);
+        let json = serde_json::to_string(&state.tx_ids).unwrap();
+        assert_eq!(json, "[[1,3],5]");
+        let resumed = round_trip(&ledger);
+        assert_eq!(resumed.tx_ids.iter().collect::<Vec<_>>(), vec![1, 2, 3, 5]);
+    }
+
+    #[test]
+    fn round_trip_preserves_sequence() {
+        let ledger = make_ledger();
+        assert_eq!(round_trip(&ledger).sequence(), 7);
+    }
+
+    #[test]
+    fn saved_state_is_deterministic() {
+        let ledger = make_ledger();
+        let mut first = Vec::new();
+        let mut second = Vec::new();
+        ledger.save_to(&mut first).unwrap();
+        round_trip(&ledger).save_to(&mut second).unwrap();
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn round_trip_preserves_dispute_window() {
+        let policy = Policy {
+            dispute_window: Some(crate::policy::DisputeWindow::Transactions(2)),
+            ..Default::default()
+        };
+        let mut ledger = Ledger::with_policy(policy);
+        ledger
+            .apply(Transaction::new(
+                TransactionType::Deposit,
+                1,
+                1,
+                Some(Decimal::ONE),
+            ))
+            .unwrap();
+        let mut buffer = Vec::new();
+        ledger.save_to(&mut buffer).unwrap();
+        let mut ledger = Ledger::load_from(buffer.as_slice(), policy, Storage::Memory).unwrap();
+        ledger
+            .apply(Transaction::new(
+                TransactionType::Deposit,
+                1,
+                2,
+                Some(Decimal::ONE),
+            ))
+            .unwrap();
+        ledger
+            .apply(Transaction::new(
+                TransactionType::Deposit,
+                1,
+                3,
+                Some(Decimal::ONE),
+            ))
+            .unwrap();
+        assert!(
+            ledger
+                .apply(Transaction::new(TransactionType::Dispute, 1, 1, None))
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn round_trip_preserves_currency_balances() {
+        let mut ledger = make_ledger();
+        for (tx, client, currency) in [(10, 1, "USD"), (11, 3, "EUR")] {
+            let mut deposit =
+                Transaction::new(TransactionType::Deposit, client, tx, Some(Decimal::TEN));
+            deposit.currency = Some(currency.to_string());
+            ledger.apply(deposit).unwrap();
+        }
+        let resumed = round_trip(&ledger);
+        assert_eq!(resumed.snapshot(), ledger.snapshot());
+        assert!(!resumed.account(3).unwrap().balances.contains_key(&None));
+    }
+
+    #[test]
+    fn round_trip_preserves_withdrawal_window() {
+        let limits = crate::limits::Limits::from_reader(
+            "client,daily_count,window_transactions\n,1,5\n".as_bytes(),
+        )
+        .unwrap();
+        let mut ledger = Ledger::new();
+        ledger.set_limits(limits.clone());
+        let mut deposit = Transaction::new(TransactionType::Deposit, 1, 1, Some(Decimal::TEN));
+        deposit.timestamp = Some(1_000);
+        ledger.apply(deposit).unwrap();
+        let mut withdrawal =
+            Transaction::new(TransactionType::Withdrawal, 1, 2, Some(Decimal::ONE));
+        withdrawal.timestamp = Some(2_000);
+        ledger.apply(withdrawal.clone()).unwrap();
+
+        let mut resumed = round_trip(&ledger);
+        resumed.set_limits(limits);
+        withdrawal.tx = 3;
+        assert!(matches!(
+            resumed.apply(withdrawal),
+            Err(AccountError::RuleViolation(
+                crate::rules::RuleError::TooManyWithdrawals(3)
+            ))
+        ));
+    }
+
+    #[test]
+    fn recorded_histories_are_saved_and_kept_recording() {
+        let mut ledger = Ledger::new();
+        ledger.record_histories();
+        ledger
+            .apply(Transaction::new(
+                TransactionType::Deposit,
+                1,
+                1,
+                Some(Decimal::ONE),
+            ))
+            .unwrap();
+        let mut resumed = round_trip(&ledger);
+        assert_eq!(
+            resumed.account(1).unwrap().history(),
+            ledger.account(1).unwrap().history()
+        );
+        for (client, tx) in [(1, 2), (2, 3)] {
+            resumed
+                .apply(Transaction::new(
+                    TransactionType::Deposit,
+                    client,
+                    tx,
+                    Some(Decimal::ONE),
+                ))
+                .unwrap();
+        }
+        assert_eq!(resumed.account(1).unwrap().history().len(), 2);
+        assert_eq!(resumed.account(2).unwrap().history().len(), 1);
+    }
+
+    #[test]
+    fn histories_are_only_saved_when_recorded() {
+        let ledger = round_trip(&make_ledger());
+        assert!(ledger.accounts().all(|account| account.history.is_none()));
+    }
+
+    #[test]
+    fn load_into_disk_storage() {
+        let path = std::env::temp_dir().join(format!("tx-cli-state-store-{}", std::process::id()));
+        let storage = Storage::Disk(crate::store::DiskFile::create(&path).unwrap());
+        let mut buffer = Vec::new();
+        make_ledger().save_to(&mut buffer).unwrap();
+        let ledger = Ledger::load_from(buffer.as_slice(), Policy::default(), storage).unwrap();
+        let mut saved = Vec::new();
+        ledger.save_to(&mut saved).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(saved, buffer);
+    }
+
+    #[test]
+    fn unsupported_version_is_rejected() {
+        let state = r#"{"version":99,"accounts":[],"tx_ids":[]}"#;
+        assert!(matches!(
+            Ledger::load_from(state.as_bytes(), Policy::default(), Storage::Memory),
+            Err(StateError::UnsupportedVersion(99))
+        ));
+    }
+
+    #[test]
+    fn first_format_version_is_rejected() {
+        let state = r#"{"version":1,"accounts":[],"tx_ids":[]}"#;
+        assert!(matches!(
+            Ledger::load_from(state.as_bytes(), Policy::default(), Storage::Memory),
+            Err(StateError::UnsupportedVersion(1))
+        ));
+    }
+
+    #[test]
+    fn save_and_load_file() {
+        let path = std::env::temp_dir().join(format!("tx-cli-state-{}.json", std::process::id()));
+        let ledger = make_ledger();
+        ledger.save(&path).unwrap();
+        let loaded = Ledger::load(&path, Policy::default(), Storage::Memory).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.snapshot(), ledger.snapshot());
+    }
+}
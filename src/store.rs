@@ -0,0 +1,545 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    fs::{File, OpenOptions},
+    io::{Read, Seek, SeekFrom, Write},
+    ops::RangeInclusive,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use crate::model::TransactionRecord;
+
+#[derive(Debug, thiserror::Error)]
+pub enum StoreError {
+    #[error("corrupt store record for transaction {0}")]
+    Corrupt(u32),
+
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+/// Storage of the transactions an account may later dispute.
+pub trait TransactionStore: fmt::Debug + Send {
+    /// Return the stored transaction, if any
+    fn get(&self, tx_id: u32) -> Result<Option<TransactionRecord>, StoreError>;
+
+    /// Store a transaction, replacing any previous record with the same ID
+    fn insert(&mut self, tx_id: u32, record: TransactionRecord) -> Result<(), StoreError>;
+
+    /// Forget a stored transaction
+    fn remove(&mut self, tx_id: u32) -> Result<(), StoreError>;
+
+    /// All stored transactions sorted by ID
+    fn records(&self) -> Result<Vec<(u32, TransactionRecord)>, StoreError>;
+
+    /// Number of stored transactions
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Default for Box<dyn TransactionStore> {
+    fn default() -> Self {
+        Box::new(MemoryStore::default())
+    }
+}
+
+/// Keeps every record in a hash map.
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    records: HashMap<u32, TransactionRecord>,
+}
+
+impl TransactionStore for MemoryStore {
+    fn get(&self, tx_id: u32) -> Result<Option<TransactionRecord>, StoreError> {
+        Ok(self.records.get(&tx_id).cloned())
+    }
+
+    fn insert(&mut self, tx_id: u32, record: TransactionRecord) -> Result<(), StoreError> {
+        self.records.insert(tx_id, record);
+        Ok(())
+    }
+
+    fn remove(&mut self, tx_id: u32) -> Result<(), StoreError> {
+        self.records.remove(&tx_id);
+        Ok(())
+    }
+
+    fn records(&self) -> Result<Vec<(u32, TransactionRecord)>, StoreError> {
+        let mut records: Vec<_> = self
+            .records
+            .iter()
+            .map(|(tx_id, record)| (*tx_id, record.clone()))
+            .collect();
+        records.sort_unstable_by_key(|(tx_id, _)| *tx_id);
+        Ok(records)
+    }
+
+    fn len(&self) -> usize {
+        self.records.len()
+    }
+}
+
+/// Append-only scratch file shared by the [`DiskStore`]s of a ledger.
+#[derive(Clone)]
+pub struct DiskFile(Arc<Mutex<File>>);
+
+impl fmt::Debug for DiskFile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("DiskFile").finish_non_exhaustive()
+    }
+}
+
+impl DiskFile {
+    /// Create the scratch file, discarding any previous content
+    pub fn create(path: &Path) -> Result<Self, StoreError> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        Ok(Self(Arc::new(Mutex::new(file))))
+    }
+
+    fn file(&self) -> std::sync::MutexGuard<'_, File> {
+        // a poisoned lock only means another shard panicked mid-write; offsets it did not
+        // hand out are never read
+        self.0
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Keeps records in a [`DiskFile`] and only an index of their offsets in memory.
+///
+/// Every insert appends a record to the file: the little-endian transaction ID and payload
+/// length followed by the JSON encoded [`TransactionRecord`]. The index points at the latest
+/// record of each transaction, so updates and removals never rewrite the file.
+#[derive(Debug)]
+pub struct DiskStore {
+    file: DiskFile,
+    index: HashMap<u32, u64>,
+}
+
+impl DiskStore {
+    /// Size of the header preceding every record: transaction ID and payload length
+    const HEADER_LEN: usize = 8;
+
+    pub fn new(file: DiskFile) -> Self {
+        Self {
+            file,
+            index: HashMap::new(),
+        }
+    }
+}
+
+impl TransactionStore for DiskStore {
+    fn get(&self, tx_id: u32) -> Result<Option<TransactionRecord>, StoreError> {
+        let Some(offset) = self.index.get(&tx_id) else {
+            return Ok(None);
+        };
+        let mut header = [0; Self::HEADER_LEN];
+        let mut payload = Vec::new();
+        {
+            let mut file = self.file.file();
+            file.seek(SeekFrom::Start(*offset))?;
+            file.read_exact(&mut header)?;
+            if header[..4] != tx_id.to_le_bytes() {
+                return Err(StoreError::Corrupt(tx_id));
+            }
+            payload.resize(
+                u32::from_le_bytes(header[4..].try_into().unwrap()) as usize,
+                0,
+            );
+            file.read_exact(&mut payload)?;
+        }
+        serde_json::from_slice(&payload)
+            .map(Some)
+            .map_err(|_| StoreError::Corrupt(tx_id))
+    }
+
+    fn insert(&mut self, tx_id: u32, record: TransactionRecord) -> Result<(), StoreError> {
+        let payload = serde_json::to_vec(&record).map_err(std::io::Error::from)?;
+        let len = u32::try_from(payload.len()).map_err(|_| {
+            std::io::Error::new(std::io::ErrorKind::InvalidInput, "store record too large")
+        })?;
+        let mut bytes = Vec::with_capacity(Self::HEADER_LEN + payload.len());
+        bytes.extend_from_slice(&tx_id.to_le_bytes());
+        bytes.extend_from_slice(&len.to_le_bytes());
+        bytes.extend_from_slice(&payload);
+        let offset = {
+            let mut file = self.file.file();
+            let offset = file.seek(SeekFrom::End(0))?;
+            file.write_all(&bytes)?;
+            offset
+        };
+        self.index.insert(tx_id, offset);
+        Ok(())
+    }
+
+    fn remove(&mut self, tx_id: u32) -> Result<(), StoreError> {
+        self.index.remove(&tx_id);
+        Ok(())
+    }
+
+    fn records(&self) -> Result<Vec<(u32, TransactionRecord)>, StoreError> {
+        let mut tx_ids: Vec<u32> = self.index.keys().copied().collect();
+        tx_ids.sort_unstable();
+        tx_ids
+            .into_iter()
+            .filter_map(|tx_id| self.get(tx_id).transpose().map(|r| r.map(|r| (tx_id, r))))
+            .collect()
+    }
+
+    fn len(&self) -> usize {
+        self.index.len()
+    }
+}
+
+/// One block of 2^16 IDs sharing their upper 16 bits.
+#[derive(Debug, Clone)]
+enum IdBlock {
+    /// Sorted lower halves, while the block is sparse
+    Sparse(Vec<u16>),
+    /// One bit per lower half, once listing them would take more room
+    Dense(Box<[u64; IdBlock::WORDS]>),
+}
+
+impl IdBlock {
+    const WORDS: usize = (1 << 16) / 64;
+
+    /// Largest sparse block, the size of a dense one
+    const SPARSE_MAX: usize = Self::WORDS * 4;
+
+    fn contains(&self, low: u16) -> bool {
+        match self {
+            Self::Sparse(lows) => lows.binary_search(&low).is_ok(),
+            Self::Dense(bits) => bits[low as usize / 64] & (1 << (low % 64)) != 0,
+        }
+    }
+
+    fn insert(&mut self, low: u16) -> bool {
+        match self {
+            Self::Sparse(lows) => {
+                let Err(index) = lows.binary_search(&low) else {
+                    return false;
+                };
+                lows.insert(index, low);
+                if lows.len() > Self::SPARSE_MAX {
+                    let mut bits = Box::new([0; Self::WORDS]);
+                    for low in lows.iter() {
+                        bits[*low as usize / 64] |= 1 << (low % 64);
+                    }
+                    *self = Self::Dense(bits);
+                }
+                true
+            }
+            Self::Dense(bits) => {
+                let word = &mut bits[low as usize / 64];
+                let bit = 1 << (low % 64);
+                let new = *word & bit == 0;
+                *word |= bit;
+                new
+            }
+        }
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            Self::Sparse(lows) => lows.len(),
+            Self::Dense(bits) => bits.iter().map(|word| word.count_ones() as usize).sum(),
+        }
+    }
+
+    fn lows(&self) -> Box<dyn Iterator<Item = u16> + '_> {
+        match self {
+            Self::Sparse(lows) => Box::new(lows.iter().copied()),
+            Self::Dense(bits) => Box::new(
+                (0..=u16::MAX).filter(|low| bits[*low as usize / 64] & (1 << (low % 64)) != 0),
+            ),
+        }
+    }
+}
+
+/// Set of transaction IDs, used for the ledger-wide registry of consumed IDs.
+///
+/// IDs are grouped in blocks by their upper 16 bits. A block lists its IDs while it holds
+/// few of them and switches to a bitmap once it fills up, so an ID costs at most two bytes
+/// and the whole `u32` range fits in 512 MiB.
+#[derive(Debug, Clone, Default)]
+pub struct TxIdSet {
+    blocks: BTreeMap<u16, IdBlock>,
+    len: usize,
+}
+
+impl TxIdSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn contains(&self, tx_id: u32) -> bool {
+        self.blocks
+            .get(&((tx_id >> 16) as u16))
+            .is_some_and(|block| block.contains(tx_id as u16))
+    }
+
+    /// Add an ID, returning whether it was not in the set yet
+    pub fn insert(&mut self, tx_id: u32) -> bool {
+        let new = self
+            .blocks
+            .entry((tx_id >> 16) as u16)
+            .or_insert_with(|| IdBlock::Sparse(Vec::new()))
+            .insert(tx_id as u16);
+        self.len += new as usize;
+        new
+    }
+
+    /// Add every ID of `range`, filling whole blocks at once
+    pub fn insert_range(&mut self, range: RangeInclusive<u32>) {
+        let (mut start, end) = range.into_inner();
+        while start <= end {
+            let block_end = start | 0xFFFF;
+            let last = end.min(block_end);
+            if start & 0xFFFF == 0 && last == block_end {
+                let full = IdBlock::Dense(Box::new([u64::MAX; IdBlock::WORDS]));
+                let old = self.blocks.insert((start >> 16) as u16, full);
+                self.len += (1 << 16) - old.map_or(0, |block| block.len());
+            } else {
+                for tx_id in start..=last {
+                    self.insert(tx_id);
+                }
+            }
+            match last.checked_add(1) {
+                Some(next) => start = next,
+                None => break,
+            }
+        }
+    }
+
+    /// All IDs in ascending order
+    pub fn iter(&self) -> impl Iterator<Item = u32> + '_ {
+        self.blocks.iter().flat_map(|(high, block)| {
+            block
+                .lows()
+                .map(move |low| (u32::from(*high) << 16) | u32::from(low))
+        })
+    }
+
+    /// All IDs as ascending runs of consecutive values
+    pub fn ranges(&self) -> impl Iterator<Item = RangeInclusive<u32>> + '_ {
+        let mut tx_ids = self.iter().peekable();
+        std::iter::from_fn(move || {
+            let start = tx_ids.next()?;
+            let mut end = start;
+            while let Some(next) = tx_ids.next_if(|tx_id| Some(*tx_id) == end.checked_add(1)) {
+                end = next;
+            }
+            Some(start..=end)
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+impl FromIterator<u32> for TxIdSet {
+    fn from_iter<I: IntoIterator<Item = u32>>(iter: I) -> Self {
+        let mut set = Self::new();
+        for tx_id in iter {
+            set.insert(tx_id);
+        }
+        set
+    }
+}
+
+/// Where the accounts of a ledger keep their transactions
+#[derive(Debug, Clone, Default)]
+pub enum Storage {
+    #[default]
+    Memory,
+    Disk(DiskFile),
+}
+
+impl Storage {
+    /// A new, empty store for one account
+    pub fn open_store(&self) -> Box<dyn TransactionStore> {
+        match self {
+            Self::Memory => Box::new(MemoryStore::default()),
+            Self::Disk(file) => Box::new(DiskStore::new(file.clone())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{TransactionKind, TransactionState};
+    use rust_decimal::Decimal;
+
+    fn temp_file(name: &str) -> (std::path::PathBuf, DiskFile) {
+        let path = std::env::temp_dir().join(format!("tx-cli-store-{}-{name}", std::process::id()));
+        let file = DiskFile::create(&path).unwrap();
+        (path, file)
+    }
+
+    fn record(
+        kind: TransactionKind,
+        amount: Decimal,
+        state: TransactionState,
+    ) -> TransactionRecord {
+        TransactionRecord {
+            kind,
+            amount,
+            state,
+            sequence: 7,
+            timestamp: None,
+            currency: None,
+            reference: None,
+            memo: None,
+        }
+    }
+
+    /// Exercise the behaviour every store must share
+    fn check_store(store: &mut dyn TransactionStore) {
+        let deposit = record(
+            TransactionKind::Deposit,
+            Decimal::new(-123456789, 4),
+            TransactionState::Processed,
+        );
+        let mut withdrawal = record(
+            TransactionKind::Withdrawal,
+            Decimal::MAX,
+            TransactionState::ChargedBack,
+        );
+        withdrawal.timestamp = Some(1_700_000_000);
+        withdrawal.memo = Some("refund \u{e9}".to_string());
+
+        assert!(store.is_empty());
+        assert_eq!(store.get(1).unwrap(), None);
+        store.insert(2, withdrawal.clone()).unwrap();
+        store.insert(1, deposit.clone()).unwrap();
+        assert_eq!(store.len(), 2);
+        assert_eq!(store.get(1).unwrap(), Some(deposit.clone()));
+        assert_eq!(store.get(2).unwrap(), Some(withdrawal.clone()));
+
+        let mut disputed = deposit.clone();
+        disputed.state = TransactionState::Disputed;
+        store.insert(1, disputed.clone()).unwrap();
+        assert_eq!(store.get(1).unwrap(), Some(disputed.clone()));
+        assert_eq!(
+            store.records().unwrap(),
+            vec![(1, disputed), (2, withdrawal)]
+        );
+
+        store.remove(2).unwrap();
+        assert_eq!(store.get(2).unwrap(), None);
+        assert_eq!(store.len(), 1);
+    }
+
+    #[test]
+    fn memory_store_behaves() {
+        check_store(&mut MemoryStore::default());
+    }
+
+    #[test]
+    fn disk_store_behaves() {
+        let (path, file) = temp_file("behaves");
+        check_store(&mut DiskStore::new(file));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn disk_stores_share_a_file() {
+        let (path, file) = temp_file("shared");
+        let mut first = DiskStore::new(file.clone());
+        let mut second = DiskStore::new(file);
+        let deposit = record(
+            TransactionKind::Deposit,
+            Decimal::ONE,
+            TransactionState::Processed,
+        );
+        first.insert(1, deposit.clone()).unwrap();
+        second.insert(2, deposit.clone()).unwrap();
+        assert_eq!(first.get(1).unwrap(), Some(deposit.clone()));
+        assert_eq!(first.get(2).unwrap(), None);
+        assert_eq!(second.get(2).unwrap(), Some(deposit));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn tx_id_set_behaves() {
+        let mut set = TxIdSet::new();
+        assert!(set.is_empty());
+        assert!(set.insert(u32::MAX));
+        assert!(set.insert(0));
+        assert!(set.insert(70_000));
+        assert!(!set.insert(70_000));
+        assert_eq!(set.len(), 3);
+        assert!(set.contains(70_000));
+        assert!(!set.contains(70_001));
+        assert_eq!(set.iter().collect::<Vec<_>>(), vec![0, 70_000, u32::MAX]);
+    }
+
+    #[test]
+    fn tx_id_set_turns_full_blocks_into_bitmaps() {
+        let ids: Vec<u32> = (0..10_000).map(|i| (1 << 16) + i * 3).collect();
+        let set: TxIdSet = ids.iter().rev().copied().collect();
+        assert!(matches!(set.blocks[&1], IdBlock::Dense(_)));
+        assert_eq!(set.len(), ids.len());
+        assert!(set.contains((1 << 16) + 3));
+        assert!(!set.contains((1 << 16) + 4));
+        assert_eq!(set.iter().collect::<Vec<_>>(), ids);
+    }
+
+    #[test]
+    fn tx_id_set_round_trips_through_ranges() {
+        let mut set: TxIdSet = [1, 2, 3, 7, u32::MAX - 1, u32::MAX].into_iter().collect();
+        assert_eq!(
+            set.ranges().collect::<Vec<_>>(),
+            vec![1..=3, 7..=7, u32::MAX - 1..=u32::MAX]
+        );
+        set.insert_range(5..=(3 << 16) + 2);
+        assert_eq!(set.len(), 3 + (3 << 16) - 2 + 2);
+        assert!(matches!(set.blocks[&1], IdBlock::Dense(_)));
+        assert_eq!(
+            set.ranges().collect::<Vec<_>>(),
+            vec![1..=3, 5..=(3 << 16) + 2, u32::MAX - 1..=u32::MAX]
+        );
+        let copy = set.ranges().fold(TxIdSet::new(), |mut copy, range| {
+            copy.insert_range(range);
+            copy
+        });
+        assert_eq!(
+            copy.iter().collect::<Vec<_>>(),
+            set.iter().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn storage_opens_matching_stores() {
+        assert!(Storage::Memory.open_store().is_empty());
+        let (path, file) = temp_file("storage");
+        let mut store = Storage::Disk(file).open_store();
+        store
+            .insert(
+                1,
+                record(
+                    TransactionKind::Deposit,
+                    Decimal::ONE,
+                    TransactionState::Processed,
+                ),
+            )
+            .unwrap();
+        assert!(std::fs::metadata(&path).unwrap().len() > 0);
+        std::fs::remove_file(path).unwrap();
+    }
+}